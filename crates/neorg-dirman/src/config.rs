@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::workspace::{Workspace, WorkspaceManager, WorkspaceNotFound};
+
+/// User-level workspace definitions.
+///
+/// ```toml
+/// default_workspace = "notes"
+///
+/// [workspaces]
+/// notes = "~/notes"
+/// work = "~/work/notes"
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+pub struct Config {
+    /// Workspace paths by their names
+    #[serde(default)]
+    pub workspaces: BTreeMap<String, PathBuf>,
+    pub default_workspace: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+}
+
+impl Config {
+    /// Returns the default location of the config file.
+    /// (e.g. `~/.config/neorg/workspaces.toml` on Linux)
+    pub fn default_path() -> Option<PathBuf> {
+        Some(dirs::config_dir()?.join("neorg").join("workspaces.toml"))
+    }
+
+    /// Reads the config file from the given path.
+    ///
+    /// * `path`: The path of the config file.
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        std::fs::read_to_string(path)?.parse()
+    }
+
+    /// Reads the config file from the default location.
+    /// Returns an empty config if the file doesn't exist.
+    pub fn load_default() -> Result<Config, ConfigError> {
+        match Self::default_path() {
+            Some(path) if path.exists() => Self::load(&path),
+            _ => Ok(Config::default()),
+        }
+    }
+
+    /// Overrides workspaces and the default workspace with the ones from `other`.
+    ///
+    /// * `other`: The config with higher priority.
+    pub fn merge(&mut self, other: Config) {
+        self.workspaces.extend(other.workspaces);
+        if other.default_workspace.is_some() {
+            self.default_workspace = other.default_workspace;
+        }
+    }
+
+    /// Returns the configured workspaces, expanding `~` in their paths.
+    /// Metadata is read from `root.toml` of each workspace while the configured name is kept.
+    pub fn workspaces(&self) -> Vec<Workspace> {
+        self.workspaces
+            .iter()
+            .map(|(name, path)| {
+                let path = expand_home(path);
+                match Workspace::load(path.clone()) {
+                    Ok(workspace) => Workspace {
+                        name: name.clone(),
+                        ..workspace
+                    },
+                    Err(_) => Workspace::new(name.clone(), path),
+                }
+            })
+            .collect()
+    }
+
+    /// Creates a workspace manager from the configured workspaces.
+    /// Returns None if no workspace is configured.
+    /// The first workspace by name is used as the default if it isn't set.
+    pub fn into_manager(self) -> Result<Option<WorkspaceManager>, WorkspaceNotFound> {
+        let workspaces = self.workspaces();
+        let Some(default_workspace) = self
+            .default_workspace
+            .or_else(|| self.workspaces.keys().next().cloned())
+        else {
+            return Ok(None);
+        };
+        WorkspaceManager::new(workspaces, default_workspace).map(Some)
+    }
+}
+
+impl FromStr for Config {
+    type Err = ConfigError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(toml::from_str(s)?)
+    }
+}
+
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "can't read config file: {err}"),
+            Self::Parse(err) => write!(f, "invalid config file: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(err: toml::de::Error) -> Self {
+        Self::Parse(err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_config() {
+        let config: Config = r#"
+            default_workspace = "work"
+
+            [workspaces]
+            work = "/notes/work"
+            wiki = "/notes/wiki"
+        "#
+        .parse()
+        .unwrap();
+        assert_eq!(config.default_workspace.as_deref(), Some("work"));
+        assert_eq!(config.workspaces["wiki"], PathBuf::from("/notes/wiki"));
+
+        let mut config = config;
+        config.merge(Config {
+            workspaces: BTreeMap::from([("wiki".to_string(), PathBuf::from("/wiki"))]),
+            default_workspace: None,
+        });
+        let manager = config.into_manager().unwrap().unwrap();
+        assert_eq!(manager.get_current_workspace().name, "work");
+        assert_eq!(
+            manager.get_workspace("wiki").unwrap().path,
+            PathBuf::from("/wiki")
+        );
+    }
+}
@@ -1,2 +1,4 @@
+pub mod config;
+pub mod metadata;
 pub mod workspace;
 pub mod workspace_traverse;
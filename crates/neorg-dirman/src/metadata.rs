@@ -0,0 +1,76 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::config::ConfigError;
+
+/// File name of the workspace metadata placed at the workspace root.
+pub const ROOT_FILE_NAME: &str = "root.toml";
+
+/// Workspace metadata declared in `root.toml`.
+///
+/// ```toml
+/// name = "notes"
+/// index = "index.norg"
+/// ignore = ["archive/**"]
+/// journal = "journal"
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct WorkspaceMetadata {
+    /// Name of the workspace used in `$name/` links
+    pub name: Option<String>,
+    /// Index file relative to the workspace root
+    pub index: Option<PathBuf>,
+    /// Glob patterns of files to exclude, relative to the workspace root
+    pub ignore: Vec<String>,
+    /// Journal folder relative to the workspace root
+    pub journal: Option<PathBuf>,
+}
+
+impl WorkspaceMetadata {
+    /// Reads `root.toml` from the given workspace root.
+    /// Returns None if the file doesn't exist.
+    ///
+    /// * `root`: The root directory of the workspace.
+    pub fn load(root: &Path) -> Result<Option<WorkspaceMetadata>, ConfigError> {
+        let path = root.join(ROOT_FILE_NAME);
+        match std::fs::read_to_string(path) {
+            Ok(text) => text.parse().map(Some),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+impl FromStr for WorkspaceMetadata {
+    type Err = ConfigError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(toml::from_str(s)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_metadata() {
+        let metadata: WorkspaceMetadata = r#"
+            name = "wiki"
+            ignore = ["archive/**"]
+        "#
+        .parse()
+        .unwrap();
+        assert_eq!(
+            metadata,
+            WorkspaceMetadata {
+                name: Some("wiki".to_string()),
+                index: None,
+                ignore: vec!["archive/**".to_string()],
+                journal: None,
+            }
+        );
+    }
+}
@@ -1,26 +1,73 @@
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::config::ConfigError;
+use crate::metadata::WorkspaceMetadata;
 
 #[derive(Debug, Clone)]
 pub struct Workspace {
     pub name: String,
     // TODO: force to use Absolute path by wrapping it to AbsPath struct
     pub path: PathBuf,
+    pub metadata: WorkspaceMetadata,
 }
 
 impl Workspace {
     pub fn new(name: String, path: PathBuf) -> Self {
-        Self { name, path }
+        Self {
+            name,
+            path,
+            metadata: WorkspaceMetadata::default(),
+        }
     }
     pub fn from(path: PathBuf) -> Self {
         Self {
             name: path.display().to_string(),
             path,
+            metadata: WorkspaceMetadata::default(),
         }
     }
+    /// Creates a workspace with metadata from `root.toml` in the given directory.
+    /// The declared name is used if exists, else the path is used as the name.
+    ///
+    /// * `path`: The root directory of the workspace.
+    pub fn load(path: PathBuf) -> Result<Self, ConfigError> {
+        let Some(metadata) = WorkspaceMetadata::load(&path)? else {
+            return Ok(Self::from(path));
+        };
+        Ok(Self {
+            name: metadata
+                .name
+                .clone()
+                .unwrap_or_else(|| path.display().to_string()),
+            path,
+            metadata,
+        })
+    }
+    /// Returns the path of the index file. (`index.norg` by default)
+    pub fn index_path(&self) -> PathBuf {
+        let index = self.metadata.index.as_deref();
+        self.path.join(index.unwrap_or(Path::new("index.norg")))
+    }
+    /// Returns the path of the journal folder if declared.
+    pub fn journal_path(&self) -> Option<PathBuf> {
+        Some(self.path.join(self.metadata.journal.as_ref()?))
+    }
+    /// Returns true if the file matches any of the ignore patterns.
+    ///
+    /// * `path`: The path of the file inside the workspace.
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.path) else {
+            return false;
+        };
+        self.metadata.ignore.iter().any(|pattern| {
+            glob::Pattern::new(pattern).is_ok_and(|pattern| pattern.matches_path(relative))
+        })
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct WorkspaceManager {
     pub workspaces: HashMap<String, Workspace>,
     current_workspace: String,
@@ -31,6 +78,14 @@ pub struct WorkspaceNotFound {
     pub workspace: String,
 }
 
+impl fmt::Display for WorkspaceNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "workspace `{}` not found", self.workspace)
+    }
+}
+
+impl std::error::Error for WorkspaceNotFound {}
+
 impl<'a> WorkspaceManager {
     /// Creates a new workspace manager with a single workspace, setting it as the default.
     ///
@@ -70,7 +125,7 @@ impl<'a> WorkspaceManager {
     /// Returns a workspace with the given name, or None if it doesn't exist.
     ///
     /// * `name`: The name of the workspace.
-    pub fn get_workspace(&self, name: &String) -> Option<&Workspace> {
+    pub fn get_workspace(&self, name: &str) -> Option<&Workspace> {
         self.workspaces.get(name)
     }
 
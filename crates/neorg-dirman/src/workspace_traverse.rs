@@ -2,7 +2,7 @@ use crate::workspace::Workspace;
 use std::path::PathBuf;
 
 impl Workspace {
-    pub fn iter_files(&self) -> impl Iterator<Item = PathBuf> {
+    pub fn iter_files(&self) -> impl Iterator<Item = PathBuf> + '_ {
         walkdir::WalkDir::new(&self.path)
             .min_depth(1)
             .into_iter()
@@ -12,6 +12,7 @@ impl Workspace {
                 if path.is_dir()
                     || path.file_name()?.to_string_lossy().starts_with('.')
                     || !path.extension().is_some_and(|ext| ext == "norg")
+                    || self.is_ignored(path)
                 {
                     return None;
                 }
@@ -0,0 +1,265 @@
+//! Persistent index of norg files.
+//!
+//! Per-file data is parsed by the caller and cached with the modification time of the file,
+//! so only changed files are parsed again on the next start.
+
+pub mod vfs;
+
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+pub use vfs::{FileSystem, VirtualFileSystem};
+
+/// Version of the DB format. DB with different version is discarded on load.
+const DB_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry<T> {
+    modified: SystemTime,
+    data: T,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Db<E> {
+    version: u32,
+    entries: E,
+}
+
+/// Returns the default location of the DB. (e.g. `~/.cache/neorg/norgberg.json` on Linux)
+pub fn default_db_path() -> Option<PathBuf> {
+    Some(dirs::cache_dir()?.join("neorg").join("norgberg.json"))
+}
+
+/// Result of indexing a directory.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct IndexStats {
+    /// Number of files parsed because they are new or changed
+    pub parsed: usize,
+    /// Number of files kept from the cache
+    pub unchanged: usize,
+    /// Number of entries removed because the file doesn't exist anymore
+    pub removed: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct NorgBerg<T, V = FileSystem> {
+    vfs: V,
+    /// Where the DB is saved. In-memory only if None.
+    db_path: Option<PathBuf>,
+    /// Shared between clones so changing a clone copies only the changed entries.
+    entries: BTreeMap<PathBuf, Arc<Entry<T>>>,
+}
+
+impl<T, V: Default> Default for NorgBerg<T, V> {
+    fn default() -> Self {
+        Self {
+            vfs: V::default(),
+            db_path: None,
+            entries: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T: Serialize + DeserializeOwned, V: VirtualFileSystem> NorgBerg<T, V> {
+    /// Opens the DB saved at `db_path`.
+    /// Starts with an empty index if the DB doesn't exist or can't be read.
+    ///
+    /// * `vfs`: The file system to read files from.
+    /// * `db_path`: The path of the DB file.
+    pub fn init(vfs: V, db_path: PathBuf) -> Self {
+        let entries = std::fs::read(&db_path)
+            .ok()
+            .and_then(|bytes| {
+                serde_json::from_slice::<Db<BTreeMap<PathBuf, Arc<Entry<T>>>>>(&bytes).ok()
+            })
+            .filter(|db| db.version == DB_VERSION)
+            .map(|db| db.entries)
+            .unwrap_or_default();
+        Self {
+            vfs,
+            db_path: Some(db_path),
+            entries,
+        }
+    }
+
+    /// Indexes files under `root`. Only files changed since the last index are parsed.
+    /// Entries under `root` which are not in `files` are removed.
+    ///
+    /// * `root`: The directory being indexed.
+    /// * `files`: All files to index under `root`.
+    /// * `parse`: Builds the data to store from the file content.
+    pub fn index(
+        &mut self,
+        root: &Path,
+        files: impl IntoIterator<Item = PathBuf>,
+        parse: impl Fn(&Path, &[u8]) -> Option<T>,
+    ) -> IndexStats {
+        let mut stats = IndexStats::default();
+        let mut indexed = HashSet::new();
+        for path in files {
+            match self.index_file(&path, &parse) {
+                Ok(true) => stats.parsed += 1,
+                Ok(false) => stats.unchanged += 1,
+                Err(_) => continue,
+            }
+            indexed.insert(path);
+        }
+        let stale: Vec<_> = self
+            .iter_under(root)
+            .map(|(path, _)| path.to_path_buf())
+            .filter(|path| !indexed.contains(path))
+            .collect();
+        for path in stale {
+            self.entries.remove(&path);
+            stats.removed += 1;
+        }
+        stats
+    }
+
+    /// Indexes a single file if it is changed since the last index.
+    /// Removes the entry if the file can't be read anymore.
+    /// Returns true if the file is parsed again.
+    ///
+    /// * `path`: The path of the file.
+    /// * `parse`: Builds the data to store from the file content.
+    pub fn index_file(
+        &mut self,
+        path: &Path,
+        parse: impl Fn(&Path, &[u8]) -> Option<T>,
+    ) -> io::Result<bool> {
+        let modified = match self.vfs.modified(path) {
+            Ok(modified) => modified,
+            Err(err) => {
+                self.entries.remove(path);
+                return Err(err);
+            }
+        };
+        if self
+            .entries
+            .get(path)
+            .is_some_and(|entry| entry.modified == modified)
+        {
+            return Ok(false);
+        }
+        let bytes = self.vfs.read_bytes(path)?;
+        let data = parse(path, &bytes)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "can't parse the file"))?;
+        self.entries
+            .insert(path.to_path_buf(), Arc::new(Entry { modified, data }));
+        Ok(true)
+    }
+
+    /// Removes the entry of the file.
+    pub fn remove(&mut self, path: &Path) {
+        self.entries.remove(path);
+    }
+
+    /// Returns the indexed data of the file.
+    pub fn get(&self, path: &Path) -> Option<&T> {
+        self.entries.get(path).map(|entry| &entry.data)
+    }
+
+    /// Iterates indexed files under `root` in path order.
+    pub fn iter_under<'a>(&'a self, root: &Path) -> impl Iterator<Item = (&'a Path, &'a T)> + 'a {
+        let root = root.to_path_buf();
+        self.entries
+            .range(root.clone()..)
+            .take_while(move |(path, _)| path.starts_with(&root))
+            .map(|(path, entry)| (path.as_path(), &entry.data))
+    }
+
+    /// Writes the DB to the disk. Does nothing for in-memory index.
+    pub fn save(&self) -> io::Result<()> {
+        let Some(db_path) = &self.db_path else {
+            return Ok(());
+        };
+        if let Some(dir) = db_path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let db = Db {
+            version: DB_VERSION,
+            entries: &self.entries,
+        };
+        // write to a temporary file first not to leave a broken DB
+        let tmp_path = db_path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec(&db)?)?;
+        std::fs::rename(tmp_path, db_path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct MemoryFileSystem {
+        files: HashMap<PathBuf, (SystemTime, String)>,
+        reads: RefCell<usize>,
+    }
+
+    impl MemoryFileSystem {
+        fn write(&mut self, path: &str, secs: u64, text: &str) {
+            let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+            self.files
+                .insert(PathBuf::from(path), (modified, text.to_string()));
+        }
+    }
+
+    impl VirtualFileSystem for MemoryFileSystem {
+        fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+            let (modified, _) = self.files.get(path).ok_or(io::ErrorKind::NotFound)?;
+            Ok(*modified)
+        }
+        fn read_bytes(&self, path: &Path) -> io::Result<Vec<u8>> {
+            *self.reads.borrow_mut() += 1;
+            let (_, text) = self.files.get(path).ok_or(io::ErrorKind::NotFound)?;
+            Ok(text.as_bytes().to_vec())
+        }
+    }
+
+    #[test]
+    fn index_changed_files() {
+        let mut vfs = MemoryFileSystem::default();
+        vfs.write("/notes/a.norg", 1, "a");
+        vfs.write("/notes/b.norg", 1, "b");
+        let mut berg: NorgBerg<String, _> = NorgBerg {
+            vfs,
+            ..Default::default()
+        };
+        let parse = |_: &Path, bytes: &[u8]| Some(String::from_utf8_lossy(bytes).into_owned());
+        let files = || {
+            vec![
+                PathBuf::from("/notes/a.norg"),
+                PathBuf::from("/notes/b.norg"),
+            ]
+        };
+
+        let stats = berg.index(Path::new("/notes"), files(), parse);
+        assert_eq!(stats.parsed, 2);
+
+        berg.vfs.write("/notes/b.norg", 2, "b2");
+        let stats = berg.index(Path::new("/notes"), files(), parse);
+        assert_eq!(stats.parsed, 1);
+        assert_eq!(stats.unchanged, 1);
+        assert_eq!(*berg.vfs.reads.borrow(), 3);
+        assert_eq!(berg.get(Path::new("/notes/b.norg")).unwrap(), "b2");
+
+        berg.vfs.files.remove(Path::new("/notes/a.norg"));
+        let stats = berg.index(
+            Path::new("/notes"),
+            vec![PathBuf::from("/notes/b.norg")],
+            parse,
+        );
+        assert_eq!(stats.removed, 1);
+        assert_eq!(berg.iter_under(Path::new("/notes")).count(), 1);
+    }
+}
@@ -0,0 +1,25 @@
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// File system access used by the index.
+/// Can be replaced to index files that aren't on the disk.
+pub trait VirtualFileSystem {
+    /// Returns the last modification time of the file.
+    fn modified(&self, path: &Path) -> io::Result<SystemTime>;
+    /// Reads the whole content of the file.
+    fn read_bytes(&self, path: &Path) -> io::Result<Vec<u8>>;
+}
+
+/// The real file system.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FileSystem;
+
+impl VirtualFileSystem for FileSystem {
+    fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+        std::fs::metadata(path)?.modified()
+    }
+    fn read_bytes(&self, path: &Path) -> io::Result<Vec<u8>> {
+        std::fs::read(path)
+    }
+}
@@ -0,0 +1,335 @@
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionTextEdit, Documentation, Position, Range,
+    TextEdit, Url,
+};
+
+use crate::{
+    document::{Document, Heading},
+    neorg::{find_named_workspace, follow_link_file, link_base_dir, workspace_for_uri},
+    norg::{LinkDestination, LinkScope, LinkWorkspace, Linkable, NorgFile},
+    session::Session,
+};
+
+/// What is being typed at the completion position
+#[derive(Debug, PartialEq)]
+enum CompletionContext {
+    /// file part of a link target. (e.g. `{:folder/fo`)
+    LinkFile {
+        root: Option<LinkWorkspace>,
+        /// root as written, kept in the completed target (e.g. `$work/`, `/`)
+        prefix: String,
+        /// column where the partial target starts
+        start: usize,
+    },
+    /// markup of an anchor. (e.g. `[anch`)
+    AnchorMarkup {
+        /// column where the partial markup starts
+        start: usize,
+    },
+    /// last scope of a link target. (e.g. `{:folder/foo:* hea`, `{# hea`)
+    LinkScope {
+        file: Option<NorgFile>,
+        kind: ScopeKind,
+        /// column where the partial scope starts
+        start: usize,
+    },
+}
+
+/// Prefix of the scope being typed
+#[derive(Debug, PartialEq)]
+enum ScopeKind {
+    /// `*`, `**`, ...
+    Heading(u16),
+    /// `#`
+    AnyHeading,
+    /// `?`
+    WikiHeading,
+}
+
+impl ScopeKind {
+    fn from_prefix(scope: &str) -> Option<Self> {
+        let level = scope.chars().take_while(|c| *c == '*').count();
+        if level > 0 {
+            return Some(Self::Heading(level as u16));
+        }
+        match scope.chars().next()? {
+            '#' => Some(Self::AnyHeading),
+            '?' => Some(Self::WikiHeading),
+            _ => None,
+        }
+    }
+
+    fn matches(&self, heading: &Heading) -> bool {
+        match self {
+            Self::Heading(level) => heading.level == *level,
+            Self::AnyHeading | Self::WikiHeading => true,
+        }
+    }
+
+    fn to_scope(&self, heading: &Heading) -> LinkScope {
+        match self {
+            Self::Heading(_) | Self::AnyHeading => {
+                LinkScope::Heading(heading.level, heading.title.clone())
+            }
+            Self::WikiHeading => LinkScope::WikiHeading(heading.title.clone()),
+        }
+    }
+}
+
+impl CompletionContext {
+    /// find completion context from text before the cursor in current line
+    fn from_line_prefix(prefix: &str) -> Option<Self> {
+        if let Some(open) = prefix.rfind('[') {
+            if prefix.rfind('{').is_none_or(|brace| brace < open) {
+                let inner = &prefix[open + 1..];
+                // `{link}[description` is not an anchor
+                if inner.contains(']') || prefix[..open].ends_with('}') {
+                    return None;
+                }
+                return Some(Self::AnchorMarkup { start: open + 1 });
+            }
+        }
+        let open = prefix.rfind('{')?;
+        let inner = &prefix[open + 1..];
+        if inner.contains('}') {
+            return None;
+        }
+        let (file, scope, scope_start) = if let Some(target) = inner.strip_prefix(':') {
+            let Some((file, scope)) = target.split_once(':') else {
+                let root = NorgFile::from_raw(target).root;
+                let prefix = match &root {
+                    None => String::new(),
+                    Some(_) if target.starts_with('/') => "/".to_string(),
+                    Some(root) => root.to_string(),
+                };
+                return Some(Self::LinkFile {
+                    root,
+                    prefix,
+                    start: open + 2,
+                });
+            };
+            (
+                Some(NorgFile::from_raw(file)),
+                scope,
+                open + 2 + file.len() + 1,
+            )
+        } else {
+            (None, inner, open + 1)
+        };
+        // only complete the last scope from scope chain (e.g. `* heading : ** sub`)
+        let (scope, scope_start) = match scope.rfind(':') {
+            Some(sep) => (&scope[sep + 1..], scope_start + sep + 1),
+            None => (scope, scope_start),
+        };
+        let trimmed = scope.trim_start();
+        Some(Self::LinkScope {
+            file,
+            kind: ScopeKind::from_prefix(trimmed)?,
+            start: scope_start + scope.len() - trimmed.len(),
+        })
+    }
+}
+
+pub fn completion(session: &Session, uri: Url, pos: Position) -> Option<Vec<CompletionItem>> {
+    let doc = session.get_document(&uri)?;
+    let line = doc.text.get_line(pos.line as usize)?.to_string();
+    let prefix = line.get(..pos.character as usize)?;
+    match CompletionContext::from_line_prefix(prefix)? {
+        CompletionContext::LinkFile {
+            root,
+            prefix,
+            start,
+        } => {
+            let range = Range::new(Position::new(pos.line, start as u32), pos);
+            complete_link_file(session, &uri, root, &prefix, range)
+        }
+        CompletionContext::AnchorMarkup { start } => {
+            let range = Range::new(Position::new(pos.line, start as u32), pos);
+            Some(complete_anchor_markup(doc, range))
+        }
+        CompletionContext::LinkScope { file, kind, start } => {
+            let range = Range::new(Position::new(pos.line, start as u32), pos);
+            complete_link_scope(session, &uri, file, kind, range)
+        }
+    }
+}
+
+/// list all norg files in the workspace as a link target written from `origin`.
+/// files of the named workspace are listed for `$name/`.
+fn complete_link_file(
+    session: &Session,
+    origin: &Url,
+    root: Option<LinkWorkspace>,
+    prefix: &str,
+    range: Range,
+) -> Option<Vec<CompletionItem>> {
+    let workspace = match &root {
+        Some(LinkWorkspace::Workspace(name)) => find_named_workspace(session, origin, name)?,
+        _ => workspace_for_uri(session, origin)?,
+    };
+    let base = link_base_dir(session, origin, root.as_ref())?;
+    let items = session
+        .workspace_files(&workspace)
+        .into_iter()
+        .filter(|(url, _)| url != origin)
+        .filter_map(|(url, summary)| {
+            let path = url.to_file_path().ok()?;
+            let file = NorgFile::relative_to(&base, &path, None)?;
+            let label = format!("{prefix}{}", file.to_string());
+            Some(CompletionItem {
+                label: label.clone(),
+                kind: Some(CompletionItemKind::FILE),
+                detail: summary
+                    .headings
+                    .first()
+                    .map(|heading| heading.title.clone()),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(range, label))),
+                ..Default::default()
+            })
+        })
+        .collect();
+    Some(items)
+}
+
+/// list headings of the link target file (or current file) matching the scope prefix
+fn complete_link_scope(
+    session: &Session,
+    origin: &Url,
+    file: Option<NorgFile>,
+    kind: ScopeKind,
+    range: Range,
+) -> Option<Vec<CompletionItem>> {
+    let headings = match file {
+        Some(file) => {
+            let target = LinkDestination::Scoped {
+                file: Some(file),
+                scope: vec![],
+            };
+            let uri = follow_link_file(session, origin, &target)?;
+            session.get_headings(&uri)?
+        }
+        None => session.get_document(origin)?.get_headings(),
+    };
+    let items = headings
+        .iter()
+        .flat_map(Heading::iter)
+        .filter(|heading| kind.matches(heading))
+        .map(|heading| {
+            let new_text = kind.to_scope(heading).to_string();
+            CompletionItem {
+                label: heading.title.clone(),
+                kind: Some(CompletionItemKind::STRUCT),
+                detail: Some("*".repeat(heading.level.into())),
+                filter_text: Some(new_text.clone()),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(range, new_text))),
+                ..Default::default()
+            }
+        })
+        .collect();
+    Some(items)
+}
+
+/// list anchor definitions in current document
+fn complete_anchor_markup(doc: &Document, range: Range) -> Vec<CompletionItem> {
+    let mut items: Vec<CompletionItem> = vec![];
+    for linkable in doc.iter_linkables() {
+        let Linkable::Anchor {
+            target: Some(target),
+            markup,
+            ..
+        } = linkable
+        else {
+            continue;
+        };
+        if items.iter().any(|item| item.label == markup) {
+            continue;
+        }
+        items.push(CompletionItem {
+            label: markup.clone(),
+            kind: Some(CompletionItemKind::REFERENCE),
+            documentation: Some(Documentation::String(format!("{{{}}}", target.to_string()))),
+            text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(range, markup))),
+            ..Default::default()
+        });
+    }
+    items
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn completion_context() {
+        assert_eq!(
+            CompletionContext::from_line_prefix("see {:fol"),
+            Some(CompletionContext::LinkFile {
+                root: None,
+                prefix: String::new(),
+                start: 6
+            })
+        );
+        assert_eq!(
+            CompletionContext::from_line_prefix("{:$/"),
+            Some(CompletionContext::LinkFile {
+                root: Some(LinkWorkspace::Current),
+                prefix: "$/".to_string(),
+                start: 2
+            })
+        );
+        assert_eq!(
+            CompletionContext::from_line_prefix("{:/fo"),
+            Some(CompletionContext::LinkFile {
+                root: Some(LinkWorkspace::Current),
+                prefix: "/".to_string(),
+                start: 2
+            })
+        );
+        assert_eq!(
+            CompletionContext::from_line_prefix("{:$work/fo"),
+            Some(CompletionContext::LinkFile {
+                root: Some(LinkWorkspace::Workspace("work".to_string())),
+                prefix: "$work/".to_string(),
+                start: 2
+            })
+        );
+        assert_eq!(
+            CompletionContext::from_line_prefix("{:folder/foo:* hea"),
+            Some(CompletionContext::LinkScope {
+                file: Some(NorgFile {
+                    root: None,
+                    path: "folder/foo".to_string()
+                }),
+                kind: ScopeKind::Heading(1),
+                start: 13
+            })
+        );
+        assert_eq!(
+            CompletionContext::from_line_prefix("{:foo:* a : ?"),
+            Some(CompletionContext::LinkScope {
+                file: Some(NorgFile {
+                    root: None,
+                    path: "foo".to_string()
+                }),
+                kind: ScopeKind::WikiHeading,
+                start: 12
+            })
+        );
+        assert_eq!(
+            CompletionContext::from_line_prefix("{** "),
+            Some(CompletionContext::LinkScope {
+                file: None,
+                kind: ScopeKind::Heading(2),
+                start: 1
+            })
+        );
+        assert_eq!(
+            CompletionContext::from_line_prefix("see [anc"),
+            Some(CompletionContext::AnchorMarkup { start: 5 })
+        );
+        assert_eq!(CompletionContext::from_line_prefix("{:foo:}[desc"), None);
+        assert_eq!(CompletionContext::from_line_prefix("[anchor] "), None);
+        assert_eq!(CompletionContext::from_line_prefix("{:foo:} "), None);
+        assert_eq!(CompletionContext::from_line_prefix("{https://"), None);
+    }
+}
@@ -0,0 +1,185 @@
+use lsp_types::{Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, Location, Url};
+
+use crate::{
+    document::Document,
+    link_graph::GraphLink,
+    neorg::find_named_workspace,
+    norg::{LinkDestination, LinkWorkspace, Linkable},
+    session::Session,
+    tree_sitter::ToLspRange as _,
+};
+
+/// collect diagnostics for a opened document
+pub fn diagnostics(session: &Session, uri: &Url) -> Vec<Diagnostic> {
+    let Some(doc) = session.get_document(uri) else {
+        return vec![];
+    };
+    // links are already resolved to the link graph
+    let mut diagnostics: Vec<_> = session
+        .graph_links(uri)
+        .iter()
+        .filter_map(|link| {
+            let message = check_link_target(session, uri, link)?;
+            Some(Diagnostic {
+                range: link.link.range.to_lsp_range(),
+                severity: Some(DiagnosticSeverity::ERROR),
+                source: Some("neorg".to_string()),
+                message,
+                ..Default::default()
+            })
+        })
+        .collect();
+    diagnostics.append(&mut anchor_diagnostics(uri, doc));
+    diagnostics
+}
+
+/// report anchor references without definition and conflicting anchor definitions
+fn anchor_diagnostics(uri: &Url, doc: &Document) -> Vec<Diagnostic> {
+    let linkables: Vec<_> = doc.iter_linkables().collect();
+    let definitions: Vec<_> = linkables
+        .iter()
+        .filter_map(|linkable| match linkable {
+            Linkable::Anchor {
+                target: Some(target),
+                markup,
+                range,
+                ..
+            } => Some((markup, target, range)),
+            _ => None,
+        })
+        .collect();
+    let mut diagnostics = vec![];
+    for linkable in linkables.iter() {
+        let Linkable::Anchor {
+            target,
+            markup,
+            range,
+            ..
+        } = linkable
+        else {
+            continue;
+        };
+        match target {
+            None => {
+                if definitions
+                    .iter()
+                    .any(|(def_markup, ..)| *def_markup == markup)
+                {
+                    continue;
+                }
+                diagnostics.push(Diagnostic {
+                    range: range.to_lsp_range(),
+                    severity: Some(DiagnosticSeverity::WARNING),
+                    source: Some("neorg".to_string()),
+                    message: format!("can't find definition of anchor `[{markup}]`"),
+                    ..Default::default()
+                });
+            }
+            Some(target) => {
+                let related: Vec<_> = definitions
+                    .iter()
+                    .filter(|(def_markup, def_target, _)| {
+                        *def_markup == markup && *def_target != target
+                    })
+                    .map(|(_, def_target, def_range)| DiagnosticRelatedInformation {
+                        location: Location::new(uri.clone(), def_range.to_lsp_range()),
+                        message: format!(
+                            "`[{markup}]` is also defined as `{{{}}}`",
+                            def_target.to_string()
+                        ),
+                    })
+                    .collect();
+                if related.is_empty() {
+                    continue;
+                }
+                diagnostics.push(Diagnostic {
+                    range: range.to_lsp_range(),
+                    severity: Some(DiagnosticSeverity::WARNING),
+                    source: Some("neorg".to_string()),
+                    message: format!("anchor `[{markup}]` is defined with different targets"),
+                    related_information: Some(related),
+                    ..Default::default()
+                });
+            }
+        }
+    }
+    diagnostics
+}
+
+/// check if the resolved link target exists.
+/// returns error message if it is broken.
+fn check_link_target(session: &Session, origin: &Url, link: &GraphLink) -> Option<String> {
+    let LinkDestination::Scoped { file, scope } = &link.link.target else {
+        return None;
+    };
+    if let Some(file) = file {
+        if let Some(LinkWorkspace::Workspace(name)) = &file.root {
+            if find_named_workspace(session, origin, name).is_none() {
+                return Some(format!("unknown workspace `${name}`"));
+            }
+        }
+        let Some(uri) = &link.target_file else {
+            return Some(format!("can't resolve file `{}`", file.to_string()));
+        };
+        if !session.file_exists(uri) {
+            return Some(format!("file `{}` doesn't exist", file.to_string()));
+        }
+    }
+    if !scope.is_empty() && link.targets.is_empty() {
+        let scope = scope
+            .iter()
+            .map(|scope| scope.to_string())
+            .collect::<Vec<_>>()
+            .join(" : ");
+        return Some(format!("can't find heading `{scope}`"));
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn report_broken_links() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("test");
+        let url = Url::from_file_path(root.join("diagnostics.norg")).unwrap();
+        let mut session = Session::default();
+        let text = "{:missing:}\n\
+                    {:$nope/foo:}\n\
+                    {:folder/foo:* nothing here}\n\
+                    {:folder/foo:* welcome to other file}\n\
+                    [undefined]\n\
+                    [a]{:folder/foo:}\n\
+                    [a]{:folder/bar:}\n";
+        session
+            .insert_document(url.clone(), Document::new(text))
+            .unwrap();
+        let diagnostics = diagnostics(&session, &url);
+        let messages: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.range.start.line, diagnostic.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                (0, "file `missing` doesn't exist"),
+                (1, "unknown workspace `$nope`"),
+                (2, "can't find heading `* nothing here`"),
+                (4, "can't find definition of anchor `[undefined]`"),
+                (5, "anchor `[a]` is defined with different targets"),
+                (6, "anchor `[a]` is defined with different targets"),
+            ]
+        );
+        let related = diagnostics[4].related_information.as_ref().unwrap();
+        assert_eq!(related.len(), 1);
+        assert_eq!(related[0].location.uri, url);
+        assert_eq!(related[0].location.range.start.line, 6);
+        assert_eq!(
+            related[0].message,
+            "`[a]` is also defined as `{:folder/bar:}`"
+        );
+    }
+}
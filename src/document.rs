@@ -1,48 +1,159 @@
-use std::path::Path;
+use std::{ops, path::Path};
 
 use norg_rs::parser::Markup;
 use ropey::Rope;
+use serde::{Deserialize, Serialize};
 use tree_sitter::{InputEdit, QueryCursor, StreamingIterator, Tree};
 
 use crate::{
-    norg::{LinkDestination, Linkable},
-    tree_sitter::{new_norg3_query, parse_norg, RopeExt, RopeProvider, ToLspRange as _},
+    norg::{LinkDestination, LinkScope, Linkable},
+    range::{PositionEncoding, Range},
+    tree_sitter::{new_norg3_query, serde_range, NorgParser, RopeExt, RopeProvider},
 };
 
 // TODO: Revisit to this type. I might not need to resolve linkables at all
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ResolvedLinkable {
     pub target: LinkDestination,
+    /// range of the written target. `None` for anchor references
+    #[serde(with = "serde_range::option")]
+    pub target_range: Option<tree_sitter::Range>,
+    #[serde(with = "serde_range")]
     pub range: tree_sitter::Range,
 }
 
+/// Heading of a section with its sub-sections
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Heading {
+    pub level: u16,
+    pub title: String,
+    /// range of the whole section
+    #[serde(with = "serde_range")]
+    pub range: tree_sitter::Range,
+    #[serde(with = "serde_range")]
+    pub title_range: tree_sitter::Range,
+    pub children: Vec<Heading>,
+}
+
+/// Anchor definition (e.g. `[markup]{target}`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnchorDefinition {
+    pub markup: String,
+    pub target: LinkDestination,
+    /// range of the whole anchor
+    #[serde(with = "serde_range")]
+    pub range: tree_sitter::Range,
+}
+
+/// Linkables, headings and anchor definitions of a document stored in the workspace index
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocumentSummary {
+    pub links: Vec<ResolvedLinkable>,
+    pub headings: Vec<Heading>,
+    pub anchors: Vec<AnchorDefinition>,
+}
+
+impl From<&Document> for DocumentSummary {
+    fn from(doc: &Document) -> Self {
+        Self {
+            links: doc.links.clone(),
+            headings: doc.get_headings(),
+            anchors: doc.anchor_definitions(),
+        }
+    }
+}
+
+impl Heading {
+    /// iterate this heading and all its sub-headings in document order
+    pub fn iter(&self) -> Box<dyn Iterator<Item = &Heading> + '_> {
+        Box::new(std::iter::once(self).chain(self.children.iter().flat_map(Heading::iter)))
+    }
+    pub fn matches(&self, scope: &LinkScope) -> bool {
+        match scope {
+            LinkScope::Heading(level, title) => {
+                self.level == *level && normalize_title(&self.title) == normalize_title(title)
+            }
+            LinkScope::WikiHeading(title) => wiki_title(&self.title) == wiki_title(title),
+        }
+    }
+}
+
+fn normalize_title(title: &str) -> String {
+    title.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// title compared by wiki links (e.g. `{? heading}`) which ignore cases
+pub fn wiki_title(title: &str) -> String {
+    normalize_title(title).to_lowercase()
+}
+
+/// Find all headings matching the scope chain (e.g. `* a : ** b`).
+/// Each scope after the first one is searched from sub-headings of the previous match.
+pub fn find_headings<'a>(headings: &'a [Heading], scope: &[LinkScope]) -> Vec<&'a Heading> {
+    let Some((first, rest)) = scope.split_first() else {
+        return vec![];
+    };
+    headings
+        .iter()
+        .flat_map(Heading::iter)
+        .filter(|heading| heading.matches(first))
+        .filter_map(|heading| {
+            rest.iter().try_fold(heading, |found, scope| {
+                found
+                    .children
+                    .iter()
+                    .flat_map(Heading::iter)
+                    .find(|heading| heading.matches(scope))
+            })
+        })
+        .collect()
+}
+
+/// Find first heading matching the scope chain
+pub fn find_heading<'a>(headings: &'a [Heading], scope: &[LinkScope]) -> Option<&'a Heading> {
+    find_headings(headings, scope).into_iter().next()
+}
+
 #[derive(Debug, Clone)]
 pub struct Document {
     pub text: Rope,
     pub tree: Tree,
-    // TODO: linkable symbols (e.g. headings) as Vec<Symbol> cached on document change
     pub links: Vec<ResolvedLinkable>,
+    /// headings read from the tree on every update
+    headings: Vec<Heading>,
+    /// version from the client. `None` for documents read from the file system
+    pub version: Option<i32>,
+    /// linkables in document order. only ones in changed ranges are captured again on update
+    linkables: Vec<Linkable>,
+    /// byte range of the text edited since the last update
+    edited: Option<ops::Range<usize>>,
+    parser: NorgParser,
 }
 
 impl Document {
     pub fn new(text: &str) -> Self {
         // parse and save tree
-        let rope = Rope::from_str(&text);
-        let tree = parse_norg(&text, None).unwrap();
-        let links = vec![];
+        let rope = Rope::from_str(text);
+        let parser = NorgParser::default();
+        let tree = parser.parse_rope(&rope, None).unwrap();
         let mut doc = Self {
             text: rope,
             tree,
-            links,
+            links: vec![],
+            headings: vec![],
+            version: None,
+            linkables: vec![],
+            edited: None,
+            parser,
         };
+        doc.linkables = doc.capture_linkables(0..doc.text.len_bytes());
         doc.links = doc.resolved_linkables();
+        doc.headings = tree_to_headings(&mut doc.tree.walk(), &doc.text);
         doc
     }
-    fn edit_from_range(&mut self, range: lsp_types::Range, insert: &str) -> InputEdit {
-        let start_byte =
-            self.text.line_to_byte(range.start.line as usize) + range.start.character as usize;
-        let end_byte =
-            self.text.line_to_byte(range.end.line as usize) + range.end.character as usize;
+    fn edit_from_range(&mut self, range: Range, insert: &str) -> InputEdit {
+        let start_byte = self.text.try_pos_to_byte(&range.start).unwrap();
+        let end_byte = self.text.try_pos_to_byte(&range.end).unwrap();
         let new_end_byte = start_byte + insert.len();
         self.text.try_remove(start_byte..end_byte).unwrap();
         self.text.try_insert(start_byte, insert).unwrap();
@@ -51,14 +162,8 @@ impl Document {
             start_byte,
             old_end_byte: end_byte,
             new_end_byte,
-            start_position: tree_sitter::Point {
-                row: range.start.line as usize,
-                column: range.start.character as usize,
-            },
-            old_end_position: tree_sitter::Point {
-                row: range.end.line as usize,
-                column: range.end.character as usize,
-            },
+            start_position: range.start.into(),
+            old_end_position: range.end.into(),
             new_end_position: tree_sitter::Point {
                 row: new_end_row,
                 column: new_end_col,
@@ -67,20 +172,83 @@ impl Document {
         return edit;
     }
 
-    pub fn change_range(&mut self, range: lsp_types::Range, text: &str) {
+    /// apply a change from the client. `range` is converted to byte columns with `encoding`.
+    pub fn change_range(
+        &mut self,
+        range: lsp_types::Range,
+        text: &str,
+        encoding: PositionEncoding,
+    ) {
+        let range = Range::from_lsp(range, &self.text, encoding);
         let edit = self.edit_from_range(range, text);
         // update text
         // edit tree
         self.tree.edit(&edit);
+        for linkable in self.linkables.iter_mut() {
+            linkable.edit(&edit);
+        }
+        let shift = |byte: usize| {
+            if byte <= edit.start_byte {
+                byte
+            } else if byte >= edit.old_end_byte {
+                byte - edit.old_end_byte + edit.new_end_byte
+            } else {
+                edit.new_end_byte
+            }
+        };
+        self.edited = Some(match self.edited.take() {
+            Some(edited) => {
+                shift(edited.start).min(edit.start_byte)..shift(edited.end).max(edit.new_end_byte)
+            }
+            None => edit.start_byte..edit.new_end_byte,
+        });
+    }
+    /// replace the whole text. the tree is parsed again from scratch so following range
+    /// changes can edit it.
+    pub fn replace_text(&mut self, text: &str) {
+        self.text = Rope::from_str(text);
+        self.tree = self.parser.parse_rope(&self.text, None).unwrap();
+        self.linkables.clear();
+        self.edited = Some(0..self.text.len_bytes());
     }
     /// apply text update to document.
-    /// this will re-parse the Tree and capture all links
+    /// this will re-parse the Tree and capture links in the changed ranges again
     pub fn update(&mut self) {
-        self.tree = parse_norg(self.text.to_string(), Some(&self.tree)).unwrap();
+        let tree = self
+            .parser
+            .parse_rope(&self.text, Some(&self.tree))
+            .unwrap();
+        // changed ranges don't include edits inside a node which keep the tree structure
+        let changed: Vec<_> = self
+            .tree
+            .changed_ranges(&tree)
+            .map(|range| range.start_byte..range.end_byte)
+            .chain(self.edited.take())
+            .collect();
+        self.tree = tree;
+        for range in changed {
+            // also capture linkables right next to the changed range
+            let range = range.start.saturating_sub(1)..range.end + 1;
+            self.linkables.retain(|linkable| {
+                let linkable = linkable.range();
+                linkable.end_byte <= range.start || range.end <= linkable.start_byte
+            });
+            let captured = self.capture_linkables(range);
+            self.linkables.extend(captured);
+        }
+        self.linkables
+            .sort_by_key(|linkable| linkable.range().start_byte);
+        self.linkables.dedup_by_key(|linkable| linkable.range());
         self.links = self.resolved_linkables();
+        self.headings = tree_to_headings(&mut self.tree.walk(), &self.text);
     }
 
-    pub fn iter_linkables(&self) -> impl Iterator<Item = Linkable> {
+    pub fn iter_linkables(&self) -> impl Iterator<Item = Linkable> + '_ {
+        self.linkables.iter().cloned()
+    }
+
+    /// capture linkables intersecting with `range` from the tree
+    fn capture_linkables(&self, range: ops::Range<usize>) -> Vec<Linkable> {
         let query_str = r#"
             ; query
             [
@@ -90,19 +258,21 @@ impl Document {
         "#;
         let query = new_norg3_query(query_str);
         let mut qry_cursor = QueryCursor::new();
+        qry_cursor.set_byte_range(range);
         let mut matches = qry_cursor.matches(
             &query,
             self.tree.root_node(),
             RopeProvider::from(&self.text),
         );
-        // TODO: avoid collecting
-        let mut links = vec![];
+        let mut linkables = vec![];
         while let Some(mat) = matches.next() {
-            links.extend(mat.captures.iter().map(|cap| cap.node).filter_map(|node| {
-                Linkable::try_from_node(node, self.text.to_string().as_bytes()).ok()
-            }));
+            linkables.extend(
+                mat.captures
+                    .iter()
+                    .filter_map(|cap| Linkable::try_from_node(cap.node, &self.text).ok()),
+            );
         }
-        links.into_iter()
+        linkables
     }
 
     pub fn resolved_linkables(&self) -> Vec<ResolvedLinkable> {
@@ -110,31 +280,35 @@ impl Document {
         let mut resolved = vec![];
         for linkable in linkables.iter() {
             resolved.push(match linkable {
-                Linkable::Link { target, range, .. } => {
+                Linkable::Link { target, target_range, range, .. } => {
                     ResolvedLinkable {
                         target: target.clone(),
+                        target_range: Some(*target_range),
                         range: *range,
                     }
                 },
-                Linkable::Anchor { target: Some(target), range, .. } => {
+                Linkable::Anchor { target: Some(target), target_range, range, .. } => {
                     ResolvedLinkable {
                         target: target.clone(),
+                        target_range: *target_range,
                         range: *range,
                     }
                 },
-                Linkable::Anchor { target: None, range, .. } => {
+                Linkable::Anchor { target: None, markup, range, .. } => {
                     let target = linkables.iter().find_map(|linkable| {
-                        let Linkable::Anchor { target: Some(target), .. } = linkable else {
+                        let Linkable::Anchor { target: Some(target), markup: def_markup, .. } = linkable else {
                             return None;
                         };
-                        Some(target)
+                        (def_markup == markup).then_some(target)
                     });
+                    // unresolved anchors are reported from diagnostics
                     let Some(target) = target else {
                         continue;
                     };
                     let target = target.clone();
                     ResolvedLinkable {
                         target,
+                        target_range: None,
                         range: *range,
                     }
                 },
@@ -143,10 +317,87 @@ impl Document {
         resolved
     }
 
-    pub fn get_symbol_tree(&self) -> Vec<lsp_types::DocumentSymbol> {
-        let mut cursor = self.tree.walk();
-        let bytes: Vec<_> = self.text.bytes().collect();
-        tree_to_symbols(&mut cursor, &bytes)
+    /// anchor definitions in document order
+    pub fn anchor_definitions(&self) -> Vec<AnchorDefinition> {
+        self.linkables
+            .iter()
+            .filter_map(|linkable| match linkable {
+                Linkable::Anchor {
+                    target: Some(target),
+                    markup,
+                    range,
+                    ..
+                } => Some(AnchorDefinition {
+                    markup: markup.clone(),
+                    target: target.clone(),
+                    range: *range,
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// find ranges of every anchor markup (both definitions and references) matching `markup`
+    pub fn find_anchor_markups(&self, markup: &str) -> Vec<tree_sitter::Range> {
+        let query = new_norg3_query("(anchor markup: (_) @markup)");
+        let mut qry_cursor = QueryCursor::new();
+        let mut captures = qry_cursor.captures(
+            &query,
+            self.tree.root_node(),
+            RopeProvider::from(&self.text),
+        );
+        let mut ranges = vec![];
+        while let Some((mat, idx)) = captures.next() {
+            let node = mat.captures[*idx].node;
+            if self.text.byte_slice(node.start_byte()..node.end_byte()) == markup {
+                ranges.push(node.range());
+            }
+        }
+        ranges
+    }
+
+    /// get section headings as a tree
+    pub fn get_headings(&self) -> Vec<Heading> {
+        self.headings.clone()
+    }
+
+    pub fn get_symbol_tree(&self, encoding: PositionEncoding) -> Vec<lsp_types::DocumentSymbol> {
+        self.get_headings()
+            .into_iter()
+            .map(|heading| self.heading_to_symbol(heading, encoding))
+            .collect()
+    }
+
+    fn heading_to_symbol(
+        &self,
+        heading: Heading,
+        encoding: PositionEncoding,
+    ) -> lsp_types::DocumentSymbol {
+        let range = Range::from(heading.range).to_lsp(&self.text, encoding);
+        let detail = self
+            .text
+            .byte_slice(heading.range.start_byte..heading.range.end_byte)
+            .to_string();
+        let children: Vec<_> = heading
+            .children
+            .into_iter()
+            .map(|child| self.heading_to_symbol(child, encoding))
+            .collect();
+        #[allow(deprecated)]
+        lsp_types::DocumentSymbol {
+            name: heading.title,
+            detail: Some(detail),
+            kind: lsp_types::SymbolKind::STRUCT,
+            tags: None,
+            range,
+            selection_range: Range::from(heading.title_range).to_lsp(&self.text, encoding),
+            children: if children.is_empty() {
+                None
+            } else {
+                Some(children)
+            },
+            deprecated: None,
+        }
     }
 
     pub fn find_anchor_definition(&self, markup: &Markup) -> Option<norg_rs::parser::AnchorDefinitionNode> {
@@ -163,58 +414,91 @@ impl TryFrom<&Path> for Document {
     }
 }
 
-fn tree_to_symbols(cursor: &mut ::tree_sitter::TreeCursor, text: &[u8]) -> Vec<lsp_types::DocumentSymbol> {
+fn tree_to_headings(cursor: &mut ::tree_sitter::TreeCursor, text: &Rope) -> Vec<Heading> {
     let node = cursor.node();
-    let mut symbols: Vec<lsp_types::DocumentSymbol> = vec![];
+    let mut headings: Vec<Heading> = vec![];
     if node.is_named() {
         match node.kind() {
             "document" => {
-                cursor.goto_first_child();
-                return tree_to_symbols(cursor, text);
+                if !cursor.goto_first_child() {
+                    return headings;
+                }
+                return tree_to_headings(cursor, text);
             }
-            "section" => {
-                // TODO: return range that can used for `name` and `selection_range`
-                // also should return the symbol type
-                let title_node = node
-                    .child_by_field_name("heading")
-                    .unwrap()
-                    .child_by_field_name("title")
-                    .unwrap();
-                let title = title_node.utf8_text(text).unwrap();
-                Some(title.to_string())
+            "section" => 'section: {
+                // skip malformed sections rather than panicking on partial trees
+                let Some(heading_node) = node.child_by_field_name("heading") else {
+                    break 'section;
+                };
+                let Some(title_node) = heading_node.child_by_field_name("title") else {
+                    break 'section;
+                };
+                let title = text.node_text(title_node);
+                let level = text
+                    .byte_slice(heading_node.start_byte()..heading_node.end_byte())
+                    .bytes()
+                    .take_while(|b| *b == b'*')
+                    .count() as u16;
+                let children = if cursor.goto_first_child() {
+                    tree_to_headings(cursor, text)
+                } else {
+                    vec![]
+                };
+                headings.push(Heading {
+                    level,
+                    title,
+                    range: node.range(),
+                    title_range: title_node.range(),
+                    children,
+                });
             }
             // TODO: add more symbols. (if final syntax has more than headings)
-            _ => None,
+            _ => {}
         }
-        .map(|name| {
-            let range = node.range().to_lsp_range();
-            #[allow(deprecated)]
-            let sym = lsp_types::DocumentSymbol {
-                name,
-                detail: Some(node.utf8_text(text).unwrap().to_string()),
-                kind: lsp_types::SymbolKind::STRUCT,
-                tags: None,
-                range,
-                selection_range: range,
-                children: if cursor.goto_first_child() {
-                    let children = tree_to_symbols(cursor, text);
-                    if children.len() > 0 {
-                        Some(children)
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                },
-                deprecated: None,
-            };
-            symbols.push(sym);
-        });
     }
     if cursor.goto_next_sibling() {
-        symbols.append(&mut tree_to_symbols(cursor, text));
+        headings.append(&mut tree_to_headings(cursor, text));
     } else {
         cursor.goto_parent();
     }
-    return symbols;
+    headings
+}
+
+#[cfg(test)]
+mod test {
+    use lsp_types::Position;
+
+    use super::*;
+
+    #[test]
+    fn update_linkables_incrementally() {
+        let mut doc = Document::new("* 제목\n{* heading}\n\n[anchor]{:file:}\n[anchor]\n");
+        let anchors = doc.anchor_definitions();
+        assert_eq!(anchors.len(), 1);
+        assert_eq!(anchors[0].markup, "anchor");
+        let changes = [
+            // edit inside a link target
+            (Position::new(1, 3), Position::new(1, 10), "title"),
+            // add a line before all links
+            (Position::new(0, 0), Position::new(0, 0), "{:other:}\n"),
+            // remove the anchor definition
+            (Position::new(4, 8), Position::new(4, 16), ""),
+        ];
+        for (start, end, text) in changes {
+            let range = lsp_types::Range::new(start, end);
+            doc.change_range(range, text, PositionEncoding::Utf16);
+            doc.update();
+            let fresh = Document::new(&doc.text.to_string());
+            assert_eq!(format!("{:?}", doc.links), format!("{:?}", fresh.links));
+            assert_eq!(
+                format!("{:?}", doc.get_headings()),
+                format!("{:?}", fresh.get_headings())
+            );
+            assert_eq!(
+                format!("{:?}", doc.anchor_definitions()),
+                format!("{:?}", fresh.anchor_definitions())
+            );
+        }
+        assert!(doc.anchor_definitions().is_empty());
+    }
 }
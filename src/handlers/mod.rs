@@ -3,24 +3,32 @@ use log::error;
 use crate::session::Session;
 
 use self::{
-    notification::{handle_did_change, handle_did_close, handle_did_open},
+    notification::{
+        handle_did_change, handle_did_change_watched_files, handle_did_change_workspace_folders,
+        handle_did_close, handle_did_open,
+    },
     request::{
-        handle_definition, handle_document_symbol, handle_references,
-        // handle_will_rename_files,
+        handle_completion, handle_definition, handle_document_symbol, handle_hover,
+        handle_prepare_rename, handle_references, handle_rename, handle_will_rename_files,
     },
 };
 
 mod notification;
 mod request;
 
-pub fn handle_req(session: &mut Session, req: lsp_server::Request) -> Option<lsp_server::Response> {
+pub use self::notification::handle_indexed;
+
+pub fn handle_req(session: &Session, req: lsp_server::Request) -> Option<lsp_server::Response> {
     error!("{}", req.method);
     match req.method.as_str() {
-        // "textDocument/completion" => todo!(),
+        "textDocument/completion" => Some(handle_completion(session, req)),
         "textDocument/documentSymbol" => Some(handle_document_symbol(session, req)),
         "textDocument/definition" => Some(handle_definition(session, req)),
         "textDocument/references" => Some(handle_references(session, req)),
-        // "workspace/willRenameFiles" => Some(handle_will_rename_files(session, req)),
+        "textDocument/hover" => Some(handle_hover(session, req)),
+        "textDocument/prepareRename" => Some(handle_prepare_rename(session, req)),
+        "textDocument/rename" => Some(handle_rename(session, req)),
+        "workspace/willRenameFiles" => Some(handle_will_rename_files(session, req)),
         _ => None,
     }
 }
@@ -28,12 +36,15 @@ pub fn handle_req(session: &mut Session, req: lsp_server::Request) -> Option<lsp
 pub fn handle_noti(
     session: &mut Session,
     noti: lsp_server::Notification,
-) -> Option<lsp_server::Response> {
+) -> Vec<lsp_server::Notification> {
     match noti.method.as_str() {
         "textDocument/didOpen" => handle_did_open(session, noti.params),
         "textDocument/didChange" => handle_did_change(session, noti.params),
         "textDocument/didClose" => handle_did_close(session, noti.params),
-        _ => (),
-    };
-    return None;
+        "workspace/didChangeWorkspaceFolders" => {
+            handle_did_change_workspace_folders(session, noti.params)
+        }
+        "workspace/didChangeWatchedFiles" => handle_did_change_watched_files(session, noti.params),
+        _ => vec![],
+    }
 }
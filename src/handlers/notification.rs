@@ -1,33 +1,160 @@
+use std::collections::HashSet;
+
+use log::error;
+use lsp_server::Notification;
 use lsp_types::{
-    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    notification::{Notification as _, PublishDiagnostics},
+    DidChangeTextDocumentParams, DidChangeWatchedFilesParams, DidChangeWorkspaceFoldersParams,
+    DidCloseTextDocumentParams, DidOpenTextDocumentParams, PublishDiagnosticsParams, Url,
 };
+use neorg_dirman::metadata::ROOT_FILE_NAME;
+use norgberg::NorgBerg;
 
-use crate::{document::Document, session::Session};
+use crate::{
+    diagnostics,
+    document::{Document, DocumentSummary},
+    neorg,
+    session::Session,
+    workspace::workspace_from_folder,
+};
 
-pub fn handle_did_open(session: &mut Session, params: serde_json::Value) {
+pub fn handle_did_open(session: &mut Session, params: serde_json::Value) -> Vec<Notification> {
     if let Ok(params) = serde_json::from_value::<DidOpenTextDocumentParams>(params) {
         let text_document = params.text_document;
+        let mut document = Document::new(&text_document.text);
+        document.version = Some(text_document.version);
         session
-            .insert_document(text_document.uri, Document::new(&text_document.text))
+            .insert_document(text_document.uri.clone(), document)
             .unwrap();
+        // workspaces found from `root.toml` are indexed on first open
+        if let Some(workspace) = neorg::workspace_for_uri(session, &text_document.uri) {
+            session.index_workspace(&workspace);
+        }
         // TODO: handle error
+        return vec![publish_diagnostics(
+            session,
+            text_document.uri,
+            Some(text_document.version),
+        )];
     }
+    vec![]
 }
 
-pub fn handle_did_change(session: &mut Session, params: serde_json::Value) {
+pub fn handle_did_change(session: &mut Session, params: serde_json::Value) -> Vec<Notification> {
     if let Ok(params) = serde_json::from_value::<DidChangeTextDocumentParams>(params) {
         let text_document = params.text_document;
         let changes = params.content_changes;
-        session
-            .update_document(&text_document.uri, changes)
-            .unwrap();
-        // TODO: handle error
+        if let Err(err) =
+            session.update_document(&text_document.uri, text_document.version, changes)
+        {
+            error!("{err}");
+            return vec![];
+        }
+        return vec![publish_diagnostics(
+            session,
+            text_document.uri,
+            Some(text_document.version),
+        )];
     }
+    vec![]
 }
 
-pub fn handle_did_close(session: &mut Session, params: serde_json::Value) {
+pub fn handle_did_close(session: &mut Session, params: serde_json::Value) -> Vec<Notification> {
     if let Ok(params) = serde_json::from_value::<DidCloseTextDocumentParams>(params) {
         let text_document = params.text_document;
         session.remove_document(&text_document.uri).unwrap();
+        // clear diagnostics of closed document
+        return vec![Notification::new(
+            PublishDiagnostics::METHOD.to_string(),
+            PublishDiagnosticsParams::new(text_document.uri, vec![], None),
+        )];
+    }
+    vec![]
+}
+
+pub fn handle_did_change_workspace_folders(
+    session: &mut Session,
+    params: serde_json::Value,
+) -> Vec<Notification> {
+    let Ok(params) = serde_json::from_value::<DidChangeWorkspaceFoldersParams>(params) else {
+        return vec![];
+    };
+    let mut changed = HashSet::new();
+    for folder in params.event.removed {
+        if let Ok(path) = folder.uri.to_file_path() {
+            changed.extend(session.remove_folder(&path));
+        }
     }
+    for folder in params.event.added.iter().filter_map(workspace_from_folder) {
+        session.index_workspace(&folder);
+        session.add_folder(folder);
+    }
+    changed
+        .into_iter()
+        .filter(|uri| session.get_document(uri).is_some())
+        .map(|uri| publish_diagnostics(session, uri, None))
+        .collect()
+}
+
+pub fn handle_did_change_watched_files(
+    session: &mut Session,
+    params: serde_json::Value,
+) -> Vec<Notification> {
+    let Ok(params) = serde_json::from_value::<DidChangeWatchedFilesParams>(params) else {
+        return vec![];
+    };
+    let mut changed = HashSet::new();
+    for event in params.changes {
+        let Ok(path) = event.uri.to_file_path() else {
+            continue;
+        };
+        if path.file_name().is_some_and(|name| name == ROOT_FILE_NAME) {
+            // workspace is added, removed or its metadata is changed
+            if let Some(root) = path.parent() {
+                session.reload_workspace(root);
+            }
+            changed.extend(session.document_urls());
+            continue;
+        }
+        changed.extend(session.update_file(&event.uri));
+    }
+    // only opened documents have diagnostics
+    changed
+        .into_iter()
+        .filter(|uri| session.get_document(uri).is_some())
+        .map(|uri| publish_diagnostics(session, uri, None))
+        .collect()
+}
+
+/// Called when the background indexing is finished.
+/// Diagnostics of opened documents are published again with links to the indexed files.
+/// Returns the indexed files whose links are not resolved yet.
+pub fn handle_indexed(
+    session: &mut Session,
+    berg: NorgBerg<DocumentSummary>,
+) -> (Vec<Notification>, Vec<Url>) {
+    let urls = session.finish_indexing(berg);
+    let notis = session
+        .document_urls()
+        .into_iter()
+        .map(|uri| publish_diagnostics(session, uri, None))
+        .collect();
+    (notis, urls)
+}
+
+fn publish_diagnostics(session: &Session, uri: Url, version: Option<i32>) -> Notification {
+    let diagnostics = diagnostics::diagnostics(session, &uri)
+        .into_iter()
+        .map(|mut diagnostic| {
+            diagnostic.range = session.encode_range(&uri, diagnostic.range);
+            for info in diagnostic.related_information.iter_mut().flatten() {
+                info.location.range = session.encode_range(&info.location.uri, info.location.range);
+            }
+            diagnostic
+        })
+        .collect();
+    Notification::new(
+        PublishDiagnostics::METHOD.to_string(),
+        PublishDiagnosticsParams::new(uri, diagnostics, version),
+    )
 }
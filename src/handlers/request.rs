@@ -1,18 +1,25 @@
 use log::error;
 use lsp_server::Response;
 use lsp_types::{
-    DocumentSymbolParams,
-    GotoDefinitionParams, GotoDefinitionResponse, ReferenceParams,
+    CompletionParams, CompletionResponse, CompletionTextEdit, DocumentSymbolParams,
+    GotoDefinitionParams, GotoDefinitionResponse, HoverParams, PrepareRenameResponse,
+    ReferenceParams, RenameFilesParams, RenameParams, TextDocumentPositionParams, Url,
 };
 
-use crate::{neorg, session::Session};
+use crate::{completion, hover, neorg, rename, session::Session};
 
 pub fn handle_document_symbol(session: &Session, req: lsp_server::Request) -> Response {
     error!("document symbol");
     let params: DocumentSymbolParams = serde_json::from_value(req.params).unwrap();
     let uri = params.text_document.uri;
-    let doc = session.get_document(&uri).unwrap();
-    let symbols = doc.get_symbol_tree();
+    let Some(doc) = session.get_document(&uri) else {
+        return Response::new_err(
+            req.id,
+            lsp_server::ErrorCode::RequestFailed as i32,
+            format!("document {uri} is not opened"),
+        );
+    };
+    let symbols = doc.get_symbol_tree(session.encoding);
     return Response::new_ok(req.id, symbols);
 }
 
@@ -20,31 +27,54 @@ pub fn handle_definition(session: &Session, req: lsp_server::Request) -> Respons
     error!("goto definition");
     let params: GotoDefinitionParams = serde_json::from_value(req.params).unwrap();
     let req_uri = params.text_document_position_params.text_document.uri;
-    let req_pos = params.text_document_position_params.position;
-    if let Some(locs) = neorg::definition(session, req_uri, req_pos) {
-        if locs.len() == 1 {
+    let req_pos = session.decode_position(&req_uri, params.text_document_position_params.position);
+    match neorg::definition(session, req_uri, req_pos).map(|locs| session.encode_locations(locs)) {
+        Ok(locs) if locs.len() == 1 => {
             Response::new_ok(req.id, GotoDefinitionResponse::Scalar(locs[0].clone()))
-        } else {
-            Response::new_ok(req.id, GotoDefinitionResponse::Array(locs))
         }
-    } else {
-        Response::new_err(
+        Ok(locs) => Response::new_ok(req.id, GotoDefinitionResponse::Array(locs)),
+        Err(err) => Response::new_err(
             req.id,
             lsp_server::ErrorCode::RequestFailed as i32,
-            "can't find link in request position".to_string(),
-        )
+            err.to_string(),
+        ),
     }
 }
 
+pub fn handle_completion(session: &Session, req: lsp_server::Request) -> Response {
+    let params: CompletionParams = serde_json::from_value(req.params).unwrap();
+    let req_uri = params.text_document_position.text_document.uri;
+    let req_pos = session.decode_position(&req_uri, params.text_document_position.position);
+    let items = completion::completion(session, req_uri.clone(), req_pos).map(|mut items| {
+        for item in items.iter_mut() {
+            if let Some(CompletionTextEdit::Edit(edit)) = &mut item.text_edit {
+                edit.range = session.encode_range(&req_uri, edit.range);
+            }
+        }
+        items
+    });
+    Response::new_ok(req.id, items.map(CompletionResponse::Array))
+}
+
+pub fn handle_hover(session: &Session, req: lsp_server::Request) -> Response {
+    let params: HoverParams = serde_json::from_value(req.params).unwrap();
+    let req_uri = params.text_document_position_params.text_document.uri;
+    let req_pos = session.decode_position(&req_uri, params.text_document_position_params.position);
+    let hover = hover::hover(session, &req_uri, req_pos).map(|mut hover| {
+        hover.range = hover
+            .range
+            .map(|range| session.encode_range(&req_uri, range));
+        hover
+    });
+    Response::new_ok(req.id, hover)
+}
+
 pub fn handle_references(session: &Session, req: lsp_server::Request) -> Response {
-    if session.workspace.is_none() {
-        todo!()
-    }
     let params: ReferenceParams = serde_json::from_value(req.params).unwrap();
     let req_uri = params.text_document_position.text_document.uri;
-    let req_pos = params.text_document_position.position;
+    let req_pos = session.decode_position(&req_uri, params.text_document_position.position);
     match neorg::references(session, req_uri, req_pos) {
-        Some(references) => Response::new_ok(req.id, references),
+        Some(references) => Response::new_ok(req.id, session.encode_locations(references)),
         None => Response::new_err(
             req.id,
             lsp_server::ErrorCode::RequestFailed as i32,
@@ -53,42 +83,89 @@ pub fn handle_references(session: &Session, req: lsp_server::Request) -> Respons
     }
 }
 
-// pub fn handle_will_rename_files(session: &Session, req: lsp_server::Request) -> Response {
-//     error!("willrename");
-//     let Some(ref workspace) = session.workspace else {
-//         // return Response::new_err(req.id, code, message)
-//         todo!()
-//     };
-//     let params: RenameFilesParams = serde_json::from_value(req.params).unwrap();
-//     let mut changes: HashMap<Url, Vec<lsp_types::TextEdit>> = HashMap::new();
-//     params.files.iter().for_each(|file_rename| {
-//         iter_links(workspace)
-//             .filter(
-//                 |(url, link)| match workspace.resolve_link_location(url, &link.destination) {
-//                     Some(loc) => loc.uri.to_string() == file_rename.old_uri,
-//                     None => false,
-//                 },
-//             )
-//             .for_each(|(link_origin, link)| {
-//                 let mut new_dest = link.destination;
-//                 new_dest.update_uri(&file_rename.new_uri).unwrap();
-//                 let textedit =
-//                     lsp_types::TextEdit::new(link.dest_range.to_lsp_range(), new_dest.to_string());
-//                 changes
-//                     .entry(link_origin)
-//                     .and_modify(|v| v.push(textedit.clone()))
-//                     .or_insert(vec![textedit]);
-//             });
-//     });
-//     let workspace_edit = lsp_types::WorkspaceEdit {
-//         changes: Some(changes),
-//         ..Default::default()
-//     };
-//     Response::new_ok(req.id, workspace_edit)
-// }
+pub fn handle_prepare_rename(session: &Session, req: lsp_server::Request) -> Response {
+    let params: TextDocumentPositionParams = serde_json::from_value(req.params).unwrap();
+    let uri = params.text_document.uri;
+    let pos = session.decode_position(&uri, params.position);
+    let range =
+        rename::prepare_rename(session, &uri, pos).map(|range| session.encode_range(&uri, range));
+    Response::new_ok(req.id, range.map(PrepareRenameResponse::Range))
+}
+
+pub fn handle_rename(session: &Session, req: lsp_server::Request) -> Response {
+    let params: RenameParams = serde_json::from_value(req.params).unwrap();
+    let req_uri = params.text_document_position.text_document.uri;
+    let req_pos = session.decode_position(&req_uri, params.text_document_position.position);
+    match rename::rename(session, &req_uri, req_pos, &params.new_name) {
+        Some(workspace_edit) => {
+            Response::new_ok(req.id, session.encode_workspace_edit(workspace_edit))
+        }
+        None => Response::new_err(
+            req.id,
+            lsp_server::ErrorCode::RequestFailed as i32,
+            "can't rename symbol in request position".to_string(),
+        ),
+    }
+}
+
+pub fn handle_will_rename_files(session: &Session, req: lsp_server::Request) -> Response {
+    error!("willrename");
+    let params: RenameFilesParams = serde_json::from_value(req.params).unwrap();
+    let files: Vec<_> = params
+        .files
+        .iter()
+        .filter_map(|file_rename| {
+            let old_uri = Url::parse(&file_rename.old_uri).ok()?;
+            let new_uri = Url::parse(&file_rename.new_uri).ok()?;
+            Some((old_uri, new_uri))
+        })
+        .collect();
+    let workspace_edit = rename::will_rename_files(session, &files);
+    Response::new_ok(req.id, session.encode_workspace_edit(workspace_edit))
+}
 
 #[cfg(test)]
 mod test {
+    use lsp_server::{Request, RequestId};
+    use lsp_types::{Position, TextDocumentIdentifier};
+
+    use super::*;
+    use crate::{document::Document, session::test::test_session};
+
+    #[test]
+    fn definitions_of_wiki_link() {
+        let (mut session, root) = test_session();
+        let path = root.join("chain.norg");
+        let url = Url::from_file_path(&path).unwrap();
+        session
+            .insert_document(url.clone(), Document::try_from(path.as_path()).unwrap())
+            .unwrap();
+        let definition = |line, character| {
+            let params = GotoDefinitionParams {
+                text_document_position_params: TextDocumentPositionParams::new(
+                    TextDocumentIdentifier::new(url.clone()),
+                    Position::new(line, character),
+                ),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            };
+            let req = Request::new(RequestId::from(1), "textDocument/definition".into(), params);
+            let resp = handle_definition(&session, req);
+            serde_json::from_value::<GotoDefinitionResponse>(resp.result.unwrap()).unwrap()
+        };
+        // `{* a}` has a single target
+        assert!(matches!(
+            definition(1, 2),
+            GotoDefinitionResponse::Scalar(loc) if loc.uri == url
+        ));
+        // `{? shared}` matches headings of two files
+        let GotoDefinitionResponse::Array(locs) = definition(2, 3) else {
+            panic!("expected multiple definitions");
+        };
+        assert_eq!(locs.len(), 2);
+        assert_eq!(locs[0].uri, url);
+    }
+
     // use lsp_types::{Position, Range, SymbolKind};
     // use tree_sitter::Parser;
     //
@@ -0,0 +1,137 @@
+use lsp_types::{Hover, HoverContents, Location, MarkupContent, MarkupKind, Position, Url};
+
+use crate::{
+    document::find_heading,
+    neorg::{follow_link_file, follow_link_target, references_to, workspace_for_uri},
+    norg::{LinkDestination, LinkScope},
+    session::Session,
+    syntax::{classify_for_decl, heading_title_at, Syntax},
+    tree_sitter::ToLspRange as _,
+};
+
+/// maximum number of lines to show in preview
+const PREVIEW_LINES: usize = 10;
+/// maximum number of referencing locations to list
+const BACKLINK_ENTRIES: usize = 5;
+
+pub fn hover(session: &Session, uri: &Url, pos: Position) -> Option<Hover> {
+    let doc = session.get_document(uri)?;
+    let syntax = classify_for_decl(&doc.tree, pos.into())?;
+    match syntax {
+        Syntax::Link(node) | Syntax::AnchorDefinition(node) => {
+            let target_node = node.child_by_field_name("target")?;
+            let target = LinkDestination::try_from_node(target_node, &doc.text).ok()?;
+            Some(Hover {
+                contents: HoverContents::Markup(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value: link_preview(session, uri, &target)?,
+                }),
+                range: Some(node.range().to_lsp_range()),
+            })
+        }
+        Syntax::Section(node) => {
+            let title_node = heading_title_at(node, pos.into())?;
+            let origin_loc = Location::new(uri.clone(), node.range().to_lsp_range());
+            let references = references_to(session, &origin_loc);
+            Some(Hover {
+                contents: HoverContents::Markup(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value: backlinks_summary(session, uri, &references),
+                }),
+                range: Some(title_node.range().to_lsp_range()),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// render resolved path of the link target with preview of its content
+fn link_preview(session: &Session, origin: &Url, target: &LinkDestination) -> Option<String> {
+    let LinkDestination::Scoped { file, scope } = target else {
+        return Some(format!("`{}`", target.to_string()));
+    };
+    let target_uri = match (file, scope.first()) {
+        // wiki links can point to headings from other files
+        (None, Some(LinkScope::WikiHeading(_))) => follow_link_target(session, origin, target)
+            .into_iter()
+            .next()
+            .map_or_else(|| origin.clone(), |loc| loc.uri),
+        _ => follow_link_file(session, origin, target)?,
+    };
+    let path = target_uri
+        .to_file_path()
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|_| target_uri.to_string());
+    let Some(target_doc) = session.load_document(&target_uri) else {
+        return Some(format!("`{path}` (file not found)"));
+    };
+    let text = if scope.is_empty() {
+        target_doc.text.to_string()
+    } else {
+        let headings = target_doc.get_headings();
+        let Some(heading) = find_heading(&headings, scope) else {
+            return Some(format!("`{path}` (heading not found)"));
+        };
+        target_doc
+            .text
+            .byte_slice(heading.range.start_byte..heading.range.end_byte)
+            .to_string()
+    };
+    Some(format!("`{path}`\n\n---\n\n{}", norg_to_markdown(&text)))
+}
+
+/// render number of references with first few referencing locations
+fn backlinks_summary(session: &Session, uri: &Url, references: &[Location]) -> String {
+    let mut summary = match references.len() {
+        1 => "**1 reference**\n".to_string(),
+        n => format!("**{n} references**\n"),
+    };
+    let root = workspace_for_uri(session, uri).map(|workspace| workspace.path);
+    for loc in references.iter().take(BACKLINK_ENTRIES) {
+        let path = match loc.uri.to_file_path() {
+            Ok(path) => root
+                .as_ref()
+                .and_then(|root| path.strip_prefix(root).ok())
+                .unwrap_or(&path)
+                .display()
+                .to_string(),
+            Err(_) => loc.uri.to_string(),
+        };
+        summary += &format!("\n- `{path}:{}`", loc.range.start.line + 1);
+    }
+    if references.len() > BACKLINK_ENTRIES {
+        summary += &format!("\n- and {} more", references.len() - BACKLINK_ENTRIES);
+    }
+    summary
+}
+
+/// Render first lines of norg text as plain markdown.
+/// Only headings are converted, other lines are shown as-is.
+fn norg_to_markdown(text: &str) -> String {
+    text.lines()
+        .take(PREVIEW_LINES)
+        .map(|line| {
+            let line = line.trim_start();
+            let level = line.chars().take_while(|c| *c == '*').count();
+            match line[level..].strip_prefix(' ') {
+                Some(title) if level > 0 => format!("{} {title}", "#".repeat(level)),
+                _ => line.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_norg_to_markdown() {
+        let text = "* heading\n  paragraph\n  ** sub heading\n  *bold* text";
+        assert_eq!(
+            norg_to_markdown(text),
+            "# heading\nparagraph\n## sub heading\n*bold* text"
+        );
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+use crossbeam_channel::{Receiver, Sender};
+use lsp_server::{Message, Notification, Request};
+use lsp_types::{
+    notification::{Notification as _, Progress},
+    request::{Request as _, WorkDoneProgressCreate},
+    NumberOrString, ProgressParams, ProgressParamsValue, WorkDoneProgress, WorkDoneProgressBegin,
+    WorkDoneProgressCreateParams, WorkDoneProgressEnd, WorkDoneProgressReport,
+};
+use neorg_dirman::workspace::Workspace;
+use norgberg::NorgBerg;
+
+use crate::{document::DocumentSummary, session::summarize};
+
+/// number of indexing started, to create unique progress tokens
+static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// Index workspaces in a background thread.
+/// The updated index is sent to the returned channel when finished.
+///
+/// * `sender`: Sender to the client, used to report progress.
+/// * `progress`: Whether the client supports `window/workDoneProgress/create`.
+pub fn spawn(
+    sender: Sender<Message>,
+    progress: bool,
+    mut berg: NorgBerg<DocumentSummary>,
+    workspaces: Vec<Workspace>,
+) -> Receiver<NorgBerg<DocumentSummary>> {
+    let (tx, rx) = crossbeam_channel::bounded(1);
+    thread::spawn(move || {
+        let reporter = progress.then(|| Reporter::create(sender));
+        let files: Vec<_> = workspaces
+            .iter()
+            .map(|workspace| (workspace, workspace.iter_files().collect::<Vec<_>>()))
+            .collect();
+        let total: usize = files.iter().map(|(_, files)| files.len()).sum();
+        let mut done = 0;
+        for (workspace, files) in files {
+            let stats = berg.index(
+                &workspace.path,
+                files.into_iter().inspect(|_| {
+                    done += 1;
+                    if let Some(reporter) = &reporter {
+                        reporter.report(done, total);
+                    }
+                }),
+                summarize,
+            );
+            log::warn!("indexed workspace {}: {stats:?}", workspace.name);
+        }
+        if let Some(reporter) = reporter {
+            reporter.end(total);
+        }
+        let _ = tx.send(berg);
+    });
+    rx
+}
+
+/// Reports indexing progress with `$/progress` notifications
+struct Reporter {
+    sender: Sender<Message>,
+    token: String,
+}
+
+impl Reporter {
+    fn create(sender: Sender<Message>) -> Self {
+        let token = format!("neorg/indexing/{}", RUNS.fetch_add(1, Ordering::Relaxed));
+        let req = Request::new(
+            token.clone().into(),
+            WorkDoneProgressCreate::METHOD.to_string(),
+            WorkDoneProgressCreateParams {
+                token: NumberOrString::String(token.clone()),
+            },
+        );
+        let _ = sender.send(Message::Request(req));
+        let reporter = Self { sender, token };
+        reporter.send(WorkDoneProgress::Begin(WorkDoneProgressBegin {
+            title: "Indexing".to_string(),
+            cancellable: Some(false),
+            message: None,
+            percentage: Some(0),
+        }));
+        reporter
+    }
+    fn report(&self, done: usize, total: usize) {
+        let percentage = (done * 100 / total.max(1)) as u32;
+        // report only when the percentage changes not to flood the client
+        if done > 1 && percentage == ((done - 1) * 100 / total.max(1)) as u32 {
+            return;
+        }
+        self.send(WorkDoneProgress::Report(WorkDoneProgressReport {
+            cancellable: Some(false),
+            message: Some(format!("{done}/{total}")),
+            percentage: Some(percentage),
+        }));
+    }
+    fn end(self, total: usize) {
+        self.send(WorkDoneProgress::End(WorkDoneProgressEnd {
+            message: Some(format!("indexed {total} files")),
+        }));
+    }
+    fn send(&self, progress: WorkDoneProgress) {
+        let noti = Notification::new(
+            Progress::METHOD.to_string(),
+            ProgressParams {
+                token: NumberOrString::String(self.token.clone()),
+                value: ProgressParamsValue::WorkDone(progress),
+            },
+        );
+        let _ = self.sender.send(Message::Notification(noti));
+    }
+}
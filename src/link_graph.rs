@@ -0,0 +1,352 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    hash::Hash,
+    sync::Arc,
+};
+
+use lsp_types::{Location, Url};
+
+use crate::{
+    document::{wiki_title, Heading, ResolvedLinkable},
+    norg::{LinkDestination, LinkScope},
+    tree_sitter::ToLspRange as _,
+};
+
+/// Link with locations it is resolved to
+#[derive(Debug, Clone)]
+pub struct GraphLink {
+    pub link: ResolvedLinkable,
+    pub targets: Vec<Location>,
+    /// file the link points to. `None` for links which can point to any file
+    /// (e.g. `{? heading}`)
+    pub target_file: Option<Url>,
+}
+
+impl GraphLink {
+    /// title searched by the wiki link (e.g. `{? heading}`)
+    fn wiki_title(&self) -> Option<String> {
+        match &self.link.target {
+            LinkDestination::Scoped { file: None, scope } => match scope.first() {
+                Some(LinkScope::WikiHeading(title)) => Some(wiki_title(title)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// files the link is resolved against
+    fn target_files(&self) -> impl Iterator<Item = &Url> {
+        self.target_file
+            .iter()
+            .chain(self.targets.iter().map(|target| &target.uri))
+    }
+}
+
+/// Forward map from files to their outbound links and reverse map from link targets to the
+/// links pointing to them.
+/// Entries are shared between clones so changing a clone copies only the changed entries.
+#[derive(Debug, Default, Clone)]
+pub struct LinkGraph {
+    links: HashMap<Url, Arc<Vec<GraphLink>>>,
+    backlinks: HashMap<Location, Arc<Vec<Location>>>,
+    /// files with links pointing to the target file
+    origins: HashMap<Url, Arc<HashSet<Url>>>,
+    /// files with wiki links searching the title
+    wiki_origins: HashMap<String, Arc<HashSet<Url>>>,
+}
+
+impl LinkGraph {
+    /// Replace outbound links of `origin`
+    pub fn insert(&mut self, origin: Url, links: Vec<GraphLink>) {
+        self.remove(&origin);
+        for link in links.iter() {
+            let loc = Location::new(origin.clone(), link.link.range.to_lsp_range());
+            for target in link.targets.iter() {
+                Arc::make_mut(self.backlinks.entry(target.clone()).or_default()).push(loc.clone());
+            }
+            for file in link.target_files() {
+                Arc::make_mut(self.origins.entry(file.clone()).or_default()).insert(origin.clone());
+            }
+            if let Some(title) = link.wiki_title() {
+                Arc::make_mut(self.wiki_origins.entry(title).or_default()).insert(origin.clone());
+            }
+        }
+        self.links.insert(origin, Arc::new(links));
+    }
+
+    /// Remove outbound links of `origin`
+    pub fn remove(&mut self, origin: &Url) {
+        let Some(links) = self.links.remove(origin) else {
+            return;
+        };
+        for target in links.iter().flat_map(|link| link.targets.iter()) {
+            if let Some(locs) = self.backlinks.get_mut(target) {
+                Arc::make_mut(locs).retain(|loc| loc.uri != *origin);
+                if locs.is_empty() {
+                    self.backlinks.remove(target);
+                }
+            }
+        }
+        for file in links.iter().flat_map(GraphLink::target_files) {
+            remove_origin(&mut self.origins, file, origin);
+        }
+        for title in links.iter().filter_map(GraphLink::wiki_title) {
+            remove_origin(&mut self.wiki_origins, &title, origin);
+        }
+    }
+
+    /// Get outbound links of `origin`
+    pub fn links(&self, origin: &Url) -> &[GraphLink] {
+        self.links.get(origin).map_or(&[], |links| links.as_slice())
+    }
+
+    /// Get locations of the links pointing to `target`
+    pub fn backlinks(&self, target: &Location) -> &[Location] {
+        self.backlinks
+            .get(target)
+            .map_or(&[], |locs| locs.as_slice())
+    }
+
+    /// Move link targets in `uri` to new ranges without resolving the links again.
+    /// Used when headings of `uri` are only moved by the edit.
+    ///
+    /// * `moved`: pairs of old and new range
+    pub fn move_targets(&mut self, uri: &Url, moved: &[(lsp_types::Range, lsp_types::Range)]) {
+        let moved: HashMap<_, _> = moved
+            .iter()
+            .map(|(old, new)| {
+                (
+                    Location::new(uri.clone(), *old),
+                    Location::new(uri.clone(), *new),
+                )
+            })
+            .collect();
+        if moved.is_empty() {
+            return;
+        }
+        // only links of the origins of `uri` can point to the moved headings
+        let origins = self.origins.get(uri).cloned().unwrap_or_default();
+        for origin in origins.iter() {
+            let Some(links) = self.links.get_mut(origin) else {
+                continue;
+            };
+            for target in Arc::make_mut(links)
+                .iter_mut()
+                .flat_map(|link| link.targets.iter_mut())
+            {
+                if let Some(new) = moved.get(target) {
+                    *target = new.clone();
+                }
+            }
+        }
+        // take all moved entries first as new ranges can overlap with old ones
+        let backlinks: Vec<_> = moved
+            .iter()
+            .filter_map(|(old, new)| Some((new.clone(), self.backlinks.remove(old)?)))
+            .collect();
+        self.backlinks.extend(backlinks);
+    }
+
+    /// Find files with links that should be resolved again when headings of `uri` are changed.
+    /// These are files linking to `uri` and files with wiki links searching the titles added to
+    /// or removed from `uri`.
+    ///
+    /// * `titles`: titles normalized by [`wiki_title`]
+    pub fn dependents(&self, uri: &Url, titles: &HashSet<String>) -> HashSet<Url> {
+        self.origins
+            .get(uri)
+            .into_iter()
+            .chain(
+                titles
+                    .iter()
+                    .filter_map(|title| self.wiki_origins.get(title)),
+            )
+            .flat_map(|origins| origins.iter())
+            .cloned()
+            .collect()
+    }
+}
+
+/// remove `origin` from the origins of `key`
+fn remove_origin<K: Hash + Eq>(map: &mut HashMap<K, Arc<HashSet<Url>>>, key: &K, origin: &Url) {
+    if let Some(origins) = map.get_mut(key) {
+        Arc::make_mut(origins).remove(origin);
+        if origins.is_empty() {
+            map.remove(key);
+        }
+    }
+}
+
+/// titles of all headings matched by wiki links
+pub fn wiki_titles(headings: &[Heading]) -> HashSet<String> {
+    headings
+        .iter()
+        .flat_map(Heading::iter)
+        .map(|heading| wiki_title(&heading.title))
+        .collect()
+}
+
+/// Files by titles of their headings to find targets of wiki links without reading every file
+#[derive(Debug, Default, Clone)]
+pub struct HeadingIndex {
+    headings: HashMap<Url, Arc<Vec<Heading>>>,
+    titles: HashMap<String, Arc<BTreeSet<Url>>>,
+}
+
+impl HeadingIndex {
+    /// Replace headings of `uri`. Returns the previous ones.
+    pub fn insert(&mut self, uri: Url, headings: Vec<Heading>) -> Option<Arc<Vec<Heading>>> {
+        let old = self.remove(&uri);
+        for heading in headings.iter().flat_map(Heading::iter) {
+            Arc::make_mut(self.titles.entry(wiki_title(&heading.title)).or_default())
+                .insert(uri.clone());
+        }
+        self.headings.insert(uri, Arc::new(headings));
+        old
+    }
+
+    /// Remove headings of `uri`. Returns the removed ones.
+    pub fn remove(&mut self, uri: &Url) -> Option<Arc<Vec<Heading>>> {
+        let headings = self.headings.remove(uri)?;
+        for heading in headings.iter().flat_map(Heading::iter) {
+            let title = wiki_title(&heading.title);
+            if let Some(uris) = self.titles.get_mut(&title) {
+                Arc::make_mut(uris).remove(uri);
+                if uris.is_empty() {
+                    self.titles.remove(&title);
+                }
+            }
+        }
+        Some(headings)
+    }
+
+    /// Get files having a heading matched by wiki links with `title`, with their headings
+    pub fn find(&self, title: &str) -> impl Iterator<Item = (&Url, &[Heading])> {
+        self.titles
+            .get(&wiki_title(title))
+            .into_iter()
+            .flat_map(|uris| uris.iter())
+            .filter_map(|uri| Some((uri, self.headings.get(uri)?.as_slice())))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use lsp_types::{Position, Range};
+
+    use super::*;
+    use crate::norg::LinkDestination;
+
+    fn link(line: usize, targets: Vec<Location>, target_file: Option<Url>) -> GraphLink {
+        let point = tree_sitter::Point {
+            row: line,
+            column: 0,
+        };
+        GraphLink {
+            link: ResolvedLinkable {
+                target: LinkDestination::Uri(String::new()),
+                target_range: None,
+                range: tree_sitter::Range {
+                    start_byte: 0,
+                    end_byte: 0,
+                    start_point: point,
+                    end_point: point,
+                },
+            },
+            targets,
+            target_file,
+        }
+    }
+
+    #[test]
+    fn update_backlinks() {
+        let a = Url::parse("file:///notes/a.norg").unwrap();
+        let b = Url::parse("file:///notes/b.norg").unwrap();
+        let heading = Location::new(
+            b.clone(),
+            Range::new(Position::new(2, 0), Position::new(4, 0)),
+        );
+        let mut graph = LinkGraph::default();
+        graph.insert(
+            a.clone(),
+            vec![link(1, vec![heading.clone()], Some(b.clone()))],
+        );
+        graph.insert(
+            b.clone(),
+            vec![link(0, vec![heading.clone()], Some(b.clone()))],
+        );
+        assert_eq!(graph.backlinks(&heading).len(), 2);
+        assert_eq!(
+            graph.dependents(&b, &HashSet::new()),
+            HashSet::from([a.clone(), b.clone()])
+        );
+
+        graph.insert(a.clone(), vec![link(3, vec![], None)]);
+        assert_eq!(
+            graph.backlinks(&heading),
+            &[Location::new(b.clone(), Range::default())]
+        );
+        assert_eq!(
+            graph.dependents(&b, &HashSet::new()),
+            HashSet::from([b.clone()])
+        );
+
+        graph.remove(&b);
+        assert!(graph.backlinks(&heading).is_empty());
+    }
+    #[test]
+    fn wiki_dependents() {
+        let a = Url::parse("file:///notes/a.norg").unwrap();
+        let b = Url::parse("file:///notes/b.norg").unwrap();
+        let c = Url::parse("file:///notes/c.norg").unwrap();
+        let heading = Location::new(
+            b.clone(),
+            Range::new(Position::new(2, 0), Position::new(4, 0)),
+        );
+        let mut wiki = link(1, vec![heading], None);
+        wiki.link.target = LinkDestination::Scoped {
+            file: None,
+            scope: vec![LinkScope::WikiHeading("Some Title".to_string())],
+        };
+        let mut graph = LinkGraph::default();
+        graph.insert(a.clone(), vec![wiki]);
+        // file the link is resolved to
+        assert_eq!(
+            graph.dependents(&b, &HashSet::new()),
+            HashSet::from([a.clone()])
+        );
+        // other files only when the title is added or removed
+        assert!(graph.dependents(&c, &HashSet::new()).is_empty());
+        let titles = HashSet::from(["other".to_string()]);
+        assert!(graph.dependents(&c, &titles).is_empty());
+        let titles = HashSet::from([wiki_title("some title")]);
+        assert_eq!(graph.dependents(&c, &titles), HashSet::from([a.clone()]));
+
+        graph.remove(&a);
+        assert!(graph.dependents(&b, &titles).is_empty());
+    }
+    #[test]
+    fn move_targets() {
+        let a = Url::parse("file:///notes/a.norg").unwrap();
+        let b = Url::parse("file:///notes/b.norg").unwrap();
+        let heading = Location::new(
+            b.clone(),
+            Range::new(Position::new(2, 0), Position::new(4, 0)),
+        );
+        let moved = Location::new(
+            b.clone(),
+            Range::new(Position::new(3, 0), Position::new(5, 0)),
+        );
+        let mut graph = LinkGraph::default();
+        graph.insert(
+            a.clone(),
+            vec![link(1, vec![heading.clone()], Some(b.clone()))],
+        );
+        graph.move_targets(&b, &[(heading.range, moved.range)]);
+        assert!(graph.backlinks(&heading).is_empty());
+        assert_eq!(graph.backlinks(&moved).len(), 1);
+
+        graph.remove(&a);
+        assert!(graph.backlinks(&moved).is_empty());
+    }
+}
@@ -1,43 +1,149 @@
+mod completion;
+mod diagnostics;
 mod document;
 mod handlers;
+mod hover;
+mod indexer;
+mod link_graph;
 mod neorg;
 mod norg;
 mod range;
+mod rename;
 mod session;
 mod syntax;
 mod tree_sitter;
+mod worker;
 mod workspace;
 
-use std::fs::File;
+use std::{
+    fs::File,
+    panic::{self, AssertUnwindSafe},
+    thread,
+    time::Duration,
+};
 
 use anyhow::Result;
+use crossbeam_channel::{after, never, select, Receiver};
 use log::{error, warn};
-use lsp_server::{Connection, Message};
+use lsp_server::{Connection, Message, RequestId};
 use lsp_types::{
-    CompletionOptions, FileOperationFilter, FileOperationPattern, FileOperationRegistrationOptions,
-    InitializeParams, OneOf, ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind,
-    WorkDoneProgressOptions, WorkspaceFileOperationsServerCapabilities,
-    WorkspaceFoldersServerCapabilities, WorkspaceServerCapabilities,
+    notification::{Cancel, DidChangeWatchedFiles, Notification as _},
+    request::{RegisterCapability, Request as _},
+    CancelParams, CompletionOptions, DidChangeWatchedFilesRegistrationOptions, FileOperationFilter,
+    FileOperationPattern, FileOperationPatternKind, FileOperationRegistrationOptions,
+    FileSystemWatcher, GlobPattern, HoverProviderCapability, InitializeParams, InitializeResult,
+    NumberOrString, OneOf, Registration, RegistrationParams, RenameOptions, ServerCapabilities,
+    TextDocumentSyncCapability, TextDocumentSyncKind, WorkDoneProgressOptions,
+    WorkspaceFileOperationsServerCapabilities, WorkspaceFolder, WorkspaceFoldersServerCapabilities,
+    WorkspaceServerCapabilities,
+};
+use neorg_dirman::{
+    config::Config,
+    workspace::{Workspace, WorkspaceManager},
 };
-use neorg_dirman::workspace::Workspace;
+use norgberg::{FileSystem, NorgBerg};
+use range::PositionEncoding;
 use session::Session;
 
-use crate::handlers::{handle_noti, handle_req};
+use crate::{
+    document::DocumentSummary,
+    handlers::{handle_indexed, handle_noti, handle_req},
+    worker::{Requests, WorkerPool},
+    workspace::workspace_from_folder,
+};
+
+/// number of indexed files whose links are resolved between messages
+const RELINK_CHUNK: usize = 64;
 
-fn main_loop(connection: Connection, mut session: Session) -> Result<()> {
+/// Handle messages from the client until the connection is closed.
+///
+/// * `progress`: Whether the client supports `window/workDoneProgress/create`.
+fn main_loop(connection: Connection, mut session: Session, progress: bool) -> Result<()> {
     error!("Server Initialized!!");
-    for msg in &connection.receiver {
-        error!("connection received msg: {:?}", msg);
-        // TODO: handle message asynchronously
-        let resp = match msg {
-            Message::Request(req) => handle_req(&mut session, req),
-            Message::Response(_) => continue,
-            Message::Notification(noti) => handle_noti(&mut session, noti),
+    let pool = WorkerPool::new(thread::available_parallelism().map_or(1, |n| n.get()));
+    let mut requests = Requests::new(pool);
+    let finished = requests.finished();
+    let mut indexed: Receiver<NorgBerg<DocumentSummary>> = never();
+    // indexed files whose links are not resolved yet
+    let mut relinking = vec![];
+    loop {
+        // workspaces found since the last indexing are indexed in the background
+        if let Some((berg, workspaces)) = session.start_indexing() {
+            indexed = indexer::spawn(connection.sender.clone(), progress, berg, workspaces);
+        }
+        // links are resolved in chunks when there's no message to handle
+        let idle = if relinking.is_empty() {
+            never()
+        } else {
+            after(Duration::ZERO)
         };
-        if let Some(resp) = resp {
-            connection.sender.send(Message::Response(resp))?;
+        select! {
+            recv(connection.receiver) -> msg => {
+                let Ok(msg) = msg else {
+                    break;
+                };
+                error!("connection received msg: {:?}", msg);
+                match msg {
+                    Message::Request(req) => {
+                        // requests don't change the session, so they are handled with a snapshot
+                        // of it while notifications are handled in order
+                        let snapshot = session.clone();
+                        requests.spawn(req.id.clone(), move || handle_req(&snapshot, req));
+                    }
+                    Message::Response(_) => continue,
+                    Message::Notification(noti) if noti.method == Cancel::METHOD => {
+                        let Ok(params) = serde_json::from_value::<CancelParams>(noti.params) else {
+                            continue;
+                        };
+                        let id = match params.id {
+                            NumberOrString::Number(id) => RequestId::from(id),
+                            NumberOrString::String(id) => RequestId::from(id),
+                        };
+                        if let Some(resp) = requests.cancel(id) {
+                            connection.sender.send(Message::Response(resp))?;
+                        }
+                    }
+                    Message::Notification(noti) => {
+                        let method = noti.method.clone();
+                        // the server keeps running with the session changed so far
+                        let notis = panic::catch_unwind(AssertUnwindSafe(|| {
+                            handle_noti(&mut session, noti)
+                        }))
+                        .unwrap_or_else(|_| {
+                            error!("{method} handler panicked");
+                            vec![]
+                        });
+                        for noti in notis {
+                            connection.sender.send(Message::Notification(noti))?;
+                        }
+                    }
+                }
+            }
+            recv(finished) -> resp => {
+                let Ok(resp) = resp else {
+                    continue;
+                };
+                if let Some(resp) = requests.finish(resp) {
+                    connection.sender.send(Message::Response(resp))?;
+                }
+            }
+            recv(indexed) -> berg => {
+                indexed = never();
+                if let Ok(berg) = berg {
+                    let (notis, urls) = handle_indexed(&mut session, berg);
+                    relinking.extend(urls);
+                    for noti in notis {
+                        connection.sender.send(Message::Notification(noti))?;
+                    }
+                }
+            }
+            recv(idle) -> _ => {
+                let chunk = relinking.split_off(relinking.len().saturating_sub(RELINK_CHUNK));
+                session.relink_files(chunk);
+            }
         }
     }
+    session.save_index();
     Ok(())
 }
 
@@ -55,7 +161,9 @@ fn main() -> Result<()> {
     let server_capabilities = ServerCapabilities {
         completion_provider: Some(CompletionOptions {
             resolve_provider: Some(false),
-            trigger_characters: Some(vec![]),
+            trigger_characters: Some(
+                [":", "/", "*", "#", "?", "["].map(String::from).to_vec(),
+            ),
             all_commit_characters: None,
             work_done_progress_options: WorkDoneProgressOptions {
                 work_done_progress: None,
@@ -66,12 +174,19 @@ fn main() -> Result<()> {
             TextDocumentSyncKind::INCREMENTAL,
         )),
         definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
         references_provider: Some(OneOf::Left(true)),
+        rename_provider: Some(OneOf::Right(RenameOptions {
+            prepare_provider: Some(true),
+            work_done_progress_options: WorkDoneProgressOptions {
+                work_done_progress: None,
+            },
+        })),
         document_symbol_provider: Some(OneOf::Left(true)),
         workspace: Some(WorkspaceServerCapabilities {
             workspace_folders: Some(WorkspaceFoldersServerCapabilities {
                 supported: Some(true),
-                ..Default::default()
+                change_notifications: Some(OneOf::Left(true)),
             }),
             file_operations: Some(WorkspaceFileOperationsServerCapabilities {
                 will_rename: Some(FileOperationRegistrationOptions {
@@ -92,6 +207,14 @@ fn main() -> Result<()> {
                             },
                             scheme: None,
                         },
+                        FileOperationFilter {
+                            pattern: FileOperationPattern {
+                                glob: "**".to_string(),
+                                matches: Some(FileOperationPatternKind::Folder),
+                                options: None,
+                            },
+                            scheme: None,
+                        },
                     ],
                 }),
                 ..Default::default()
@@ -99,17 +222,131 @@ fn main() -> Result<()> {
         }),
         ..Default::default()
     };
-    let init_params = connection.initialize(serde_json::to_value(server_capabilities)?)?;
+    let (init_id, init_params) = connection.initialize_start()?;
     let init_params: InitializeParams = serde_json::from_value(init_params)?;
-    let workspace = init_params
-        .root_uri
+    let encoding = init_params
+        .capabilities
+        .general
         .as_ref()
-        .filter(|uri| uri.scheme() == "file")
-        .and_then(|uri| uri.to_file_path().ok())
-        .map(Workspace::from);
-    let session = Session::with_workspace(workspace);
-    main_loop(connection, session)?;
+        .and_then(|general| general.position_encodings.as_deref())
+        .map_or_else(PositionEncoding::default, PositionEncoding::negotiate);
+    let server_capabilities = ServerCapabilities {
+        position_encoding: Some(encoding.kind()),
+        ..server_capabilities
+    };
+    let init_result = InitializeResult {
+        capabilities: server_capabilities,
+        server_info: None,
+    };
+    connection.initialize_finish(init_id, serde_json::to_value(init_result)?)?;
+    let berg = match norgberg::default_db_path() {
+        Some(db_path) => NorgBerg::init(FileSystem, db_path),
+        None => NorgBerg::default(),
+    };
+    let mut session = Session::new(
+        load_workspaces(&init_params),
+        workspace_folders(&init_params),
+        berg,
+    );
+    session.encoding = encoding;
+    let watch_files = init_params
+        .capabilities
+        .workspace
+        .as_ref()
+        .and_then(|workspace| workspace.did_change_watched_files)
+        .and_then(|capability| capability.dynamic_registration)
+        .unwrap_or(false);
+    if watch_files {
+        connection
+            .sender
+            .send(Message::Request(register_file_watchers()))?;
+    }
+    let progress = init_params
+        .capabilities
+        .window
+        .as_ref()
+        .and_then(|window| window.work_done_progress)
+        .unwrap_or(false);
+    main_loop(connection, session, progress)?;
     iothreads.join()?;
     warn!("shut down");
     Ok(())
 }
+
+/// Load workspaces from the config file, overridden by `initializationOptions`.
+/// `default_workspace` is ignored by the server since `$/` in links always refers to the
+/// workspace containing the document. It is only checked so the config stays valid for dirman.
+fn load_workspaces(init_params: &InitializeParams) -> Option<WorkspaceManager> {
+    let mut config = Config::load_default().unwrap_or_else(|err| {
+        error!("{err}");
+        Config::default()
+    });
+    if let Some(options) = init_params.initialization_options.clone() {
+        match serde_json::from_value::<Config>(options) {
+            Ok(options) => config.merge(options),
+            Err(err) => error!("invalid initializationOptions: {err}"),
+        }
+    }
+    config
+        .clone()
+        .into_manager()
+        .or_else(|err| {
+            warn!("{err}, using the first workspace as the default");
+            Config {
+                default_workspace: None,
+                ..config
+            }
+            .into_manager()
+        })
+        .unwrap_or_else(|err| {
+            error!("{err}");
+            None
+        })
+}
+
+/// Request to watch norg files and workspace metadata changed outside of the client
+fn register_file_watchers() -> lsp_server::Request {
+    let options = DidChangeWatchedFilesRegistrationOptions {
+        watchers: ["**/*.norg", "**/root.toml"]
+            .map(|pattern| FileSystemWatcher {
+                glob_pattern: GlobPattern::String(pattern.to_string()),
+                kind: None,
+            })
+            .to_vec(),
+    };
+    lsp_server::Request::new(
+        "register-file-watchers".to_string().into(),
+        RegisterCapability::METHOD.to_string(),
+        RegistrationParams {
+            registrations: vec![Registration {
+                id: "file-watchers".to_string(),
+                method: DidChangeWatchedFiles::METHOD.to_string(),
+                register_options: serde_json::to_value(options).ok(),
+            }],
+        },
+    )
+}
+
+/// Workspace folders opened in the client. Falls back to `rootUri` for older clients.
+fn workspace_folders(init_params: &InitializeParams) -> Vec<Workspace> {
+    #[allow(deprecated)]
+    let folders = init_params.workspace_folders.clone().unwrap_or_else(|| {
+        init_params
+            .root_uri
+            .iter()
+            .map(|uri| WorkspaceFolder {
+                uri: uri.clone(),
+                name: uri
+                    .path_segments()
+                    .and_then(|mut segments| segments.rfind(|s| !s.is_empty()))
+                    .unwrap_or_default()
+                    .to_string(),
+            })
+            .collect()
+    });
+    folders
+        .iter()
+        .filter(|folder| folder.uri.scheme() == "file")
+        .filter_map(workspace_from_folder)
+        .collect()
+}
@@ -1,14 +1,16 @@
 use std::path::{Path, PathBuf};
 
+use anyhow::{bail, Context as _};
 use lsp_types::{Location, Position, Url};
-use neorg_dirman::workspace::Workspace;
+use neorg_dirman::{metadata::ROOT_FILE_NAME, workspace::Workspace};
 
 use crate::{
-    norg::{LinkDestination, LinkWorkspace, NorgFile},
+    document::{find_heading, find_headings},
+    norg::{LinkDestination, LinkScope, LinkWorkspace, NorgFile},
     range::Range,
     session::Session,
     syntax::{classify_for_decl, Syntax},
-    tree_sitter::{ToLspRange, ToLspRangeWith as _},
+    tree_sitter::{RopeExt as _, ToLspRange, ToLspRangeWith as _},
     workspace::WorkspaceExt as _,
 };
 
@@ -20,85 +22,150 @@ use crate::{
 // API, the API should in theory be usable as a library, or via a different
 // protocol.
 
-pub fn definition(session: &Session, uri: Url, pos: Position) -> Option<Vec<Location>> {
+pub fn definition(session: &Session, uri: Url, pos: Position) -> anyhow::Result<Vec<Location>> {
     // 1. from given url & pos, get linkable(reference)
     // 2. if linkable is an anchor reference, return matching anchor definition
     // 3. query location with that target
     //    (this should be done with global session to handle multiple workspaces)
     // 4. return queried location as definition
-    let doc = session.get_document(&uri).unwrap();
-    let syntax = classify_for_decl(&doc.tree, pos.into())?;
+    let doc = session.get_document(&uri).context("document is not opened")?;
+    let syntax =
+        classify_for_decl(&doc.tree, pos.into()).context("can't find link in request position")?;
     match syntax {
         Syntax::Link(node) | Syntax::AnchorDefinition(node) => {
-            let target_node = node.child_by_field_name("target")?;
-            let target =
-                LinkDestination::try_from_node(target_node, doc.text.to_string().as_bytes())
-                    .ok()?;
-            follow_link_target(&session, &uri, &target).map(|loc| vec![loc])
+            let target_node = node
+                .child_by_field_name("target")
+                .context("can't find 'target' field from node")?;
+            let target = LinkDestination::try_from_node(target_node, &doc.text)?;
+            check_link_workspace(session, &uri, &target)?;
+            let locs = follow_link_target(session, &uri, &target);
+            if locs.is_empty() {
+                bail!("can't find link target `{}`", target.to_string());
+            }
+            Ok(locs)
         }
         Syntax::AnchorReference(node) => {
             // go to anchor definition
-            let markup_node = node.child_by_field_name("markup")?;
-            let markup = markup_node.utf8_text(doc.text.to_string().as_bytes()).unwrap().to_string();
-            let def_node = doc.find_anchor_definition(&markup)?;
+            let markup_node = node
+                .child_by_field_name("markup")
+                .context("can't find 'markup' field from node")?;
+            let markup = doc.text.node_text(markup_node);
+            let def_node = doc
+                .find_anchor_definition(&markup)
+                .with_context(|| format!("can't find definition of anchor `[{markup}]`"))?;
             let loc = Location::new(uri, def_node.range.to_lsp_range_with(&doc.text));
             log::error!("{loc:?}");
-            Some(vec![loc])
+            Ok(vec![loc])
         }
-        _ => None,
+        _ => bail!("can't find link in request position"),
     }
 }
 
+/// Check if the named workspace in link target is known to the session
+fn check_link_workspace(
+    session: &Session,
+    origin: &Url,
+    target: &LinkDestination,
+) -> anyhow::Result<()> {
+    if let LinkDestination::Scoped {
+        file: Some(NorgFile {
+            root: Some(LinkWorkspace::Workspace(name)),
+            ..
+        }),
+        ..
+    } = target
+    {
+        if find_named_workspace(session, origin, name).is_none() {
+            bail!("unknown workspace `${name}`");
+        }
+    }
+    Ok(())
+}
+
 pub fn references(session: &Session, req_uri: Url, pos: Position) -> Option<Vec<Location>> {
     // 1. from given url & pos, get linkable(reference) or referenceable
     //    > for linkable(reference), get definition for it using same logic from `definition()`
     // 2. find all linkables pointing to gained definition (referenceable)
-    let doc = session.get_document(&req_uri).unwrap();
+    let doc = session.get_document(&req_uri)?;
     let syntax = classify_for_decl(&doc.tree, pos.into())?;
     let origin_loc = match syntax {
         // reference
         Syntax::Link(node) | Syntax::AnchorDefinition(node) => {
             let target_node = node.child_by_field_name("target")?;
-            let target =
-                LinkDestination::try_from_node(target_node, doc.text.to_string().as_bytes())
-                    .ok()?;
-            follow_link_target(&session, &req_uri, &target)?
+            let target = LinkDestination::try_from_node(target_node, &doc.text).ok()?;
+            follow_link_target(session, &req_uri, &target)
+                .into_iter()
+                .next()?
         }
         // referenceable
         Syntax::Section(node) => {
             let range = Range::from(node.range());
             lsp_types::Location::new(req_uri.clone(), range.into())
         }
-        // reference of a reference
-        Syntax::AnchorReference(..) => {
-            todo!("what should I do with anchor reference")
+        // reference of a reference. resolved to the target of its definition
+        Syntax::AnchorReference(node) => {
+            let link = doc.links.iter().find(|link| link.range == node.range())?;
+            follow_link_target(session, &req_uri, &link.target)
+                .into_iter()
+                .next()?
         }
     };
-    Some(
-        if let Some(workspace) = find_workspace_for_uri(&req_uri) {
-            workspace
-                .iter_linkables_with(|uri| session.get_document(uri).cloned())
-                .filter(|(uri, link)| {
-                    follow_link_target(session, &uri, &link.target)
-                        .is_some_and(|loc| loc == origin_loc)
-                })
-                .map(|(uri, link)| Location::new(uri.clone(), link.range.to_lsp_range()))
-                .collect()
-        } else {
-            doc.links
-                .iter()
-                .filter(|link| {
-                    follow_link_target(session, &req_uri, &link.target)
-                        .is_some_and(|loc| loc == origin_loc)
-                })
-                .map(|link| Location::new(req_uri.clone(), link.range.to_lsp_range()))
-                .collect()
-        }
-    )
+    Some(references_to(session, &origin_loc))
 }
 
+/// Find all linkables pointing to `origin_loc` from indexed files and opened documents.
+pub fn references_to(session: &Session, origin_loc: &Location) -> Vec<Location> {
+    session.backlinks(origin_loc).to_vec()
+}
+
+/// Find locations the link target points to.
+/// Wiki links without file (e.g. `{? heading}`) can match multiple headings over the
+/// workspace. Those are ordered with matches from `origin` first.
 pub fn follow_link_target(
-    _session: &Session,
+    session: &Session,
+    origin: &Url,
+    target: &LinkDestination,
+) -> Vec<lsp_types::Location> {
+    match target {
+        LinkDestination::Scoped { file: None, scope }
+            if matches!(scope.first(), Some(LinkScope::WikiHeading(_))) =>
+        {
+            find_wiki_headings(session, origin, scope)
+        }
+        _ => resolve_link_target(session, origin, target)
+            .into_iter()
+            .collect(),
+    }
+}
+
+/// Search headings matching the scope from every document in the workspace
+fn find_wiki_headings(session: &Session, origin: &Url, scope: &[LinkScope]) -> Vec<Location> {
+    let Some(LinkScope::WikiHeading(title)) = scope.first() else {
+        return vec![];
+    };
+    let workspace = workspace_for_uri(session, origin);
+    let in_workspace = |uri: &Url| {
+        workspace.as_ref().is_some_and(|workspace| {
+            uri.to_file_path().is_ok_and(|path| {
+                path.starts_with(&workspace.path) && !workspace.is_ignored(&path)
+            })
+        })
+    };
+    let (mut locations, others): (Vec<_>, Vec<_>) = session
+        .find_wiki_files(title)
+        .filter(|(uri, _)| *uri == origin || in_workspace(uri))
+        .flat_map(|(uri, headings)| {
+            find_headings(headings, scope)
+                .into_iter()
+                .map(|heading| Location::new(uri.clone(), heading.range.to_lsp_range()))
+        })
+        .partition(|loc| loc.uri == *origin);
+    locations.extend(others);
+    locations
+}
+
+fn resolve_link_target(
+    session: &Session,
     origin: &Url,
     target: &LinkDestination,
 ) -> Option<lsp_types::Location> {
@@ -109,43 +176,121 @@ pub fn follow_link_target(
         },
         LinkDestination::Scoped {
             file: Some(NorgFile { root, path }),
-            scope: _,
+            scope,
         } => {
             let real_path = if !path.ends_with(".norg") {
                 path.clone() + ".norg"
             } else {
                 path.clone()
             };
-            let uri = match root {
+            let workspace = match root {
+                None => None,
+                Some(LinkWorkspace::Current) => Some(workspace_for_uri(session, origin)?),
+                Some(LinkWorkspace::Workspace(name)) => {
+                    Some(find_named_workspace(session, origin, name)?)
+                }
+            };
+            let uri = match workspace {
                 None => {
                     let path = origin.join(&real_path).ok()?;
                     log::error!("{path}");
                     path
                 }
-                Some(LinkWorkspace::Current) => {
-                    let workspace = find_workspace_for_uri(&origin)?;
+                // link to the workspace root points to its index file
+                Some(workspace) if path.is_empty() => {
+                    Url::from_file_path(workspace.index_path()).ok()?
+                }
+                Some(workspace) => {
                     let workspace_url = workspace.get_url().ok()?;
                     workspace_url.join(&real_path).ok()?
                 }
-                Some(LinkWorkspace::Workspace(_name)) => {
-                    todo!("get workspace name from session")
-                }
             };
+            let range = resolve_scope(session, &uri, scope)?;
+            lsp_types::Location { uri, range }
+        }
+        LinkDestination::Scoped { file: None, scope } => {
+            let range = resolve_scope(session, origin, scope)?;
             lsp_types::Location {
-                uri,
-                range: Default::default(),
+                uri: origin.clone(),
+                range,
             }
         }
+    })
+}
+
+/// Find range of the section matching the scope chain in document of `uri`.
+/// Empty scope points to the start of the document.
+fn resolve_scope(session: &Session, uri: &Url, scope: &[LinkScope]) -> Option<lsp_types::Range> {
+    if scope.is_empty() {
+        return Some(Default::default());
+    }
+    let headings = session.get_headings(uri)?;
+    let heading = find_heading(&headings, scope)?;
+    Some(heading.range.to_lsp_range())
+}
+
+/// Follow only the file part of the link target, ignoring its scopes.
+/// Links without file part point to the `origin` itself.
+pub fn follow_link_file(session: &Session, origin: &Url, target: &LinkDestination) -> Option<Url> {
+    let target = match target {
+        LinkDestination::Scoped {
+            file: Some(file),
+            scope: _,
+        } => LinkDestination::Scoped {
+            file: Some(file.clone()),
+            scope: vec![],
+        },
         LinkDestination::Scoped {
             file: None,
             scope: _,
-        } => {
-            unimplemented!("scope is not implemented yet")
+        } => return Some(origin.clone()),
+        LinkDestination::Uri(_) => target.clone(),
+    };
+    follow_link_target(session, origin, &target)
+        .into_iter()
+        .next()
+        .map(|loc| loc.uri)
+}
+
+/// Get directory where the file part of the link is relative to.
+pub fn link_base_dir(
+    session: &Session,
+    origin: &Url,
+    root: Option<&LinkWorkspace>,
+) -> Option<PathBuf> {
+    match root {
+        None => Some(origin.to_file_path().ok()?.parent()?.to_path_buf()),
+        Some(LinkWorkspace::Current) => Some(workspace_for_uri(session, origin)?.path),
+        Some(LinkWorkspace::Workspace(name)) => {
+            Some(find_named_workspace(session, origin, name)?.path)
         }
-    })
+    }
+}
+
+/// Find workspace by the name used in `$name/` links.
+/// Workspace containing `origin` can be also referred by its declared name.
+pub fn find_named_workspace(session: &Session, origin: &Url, name: &str) -> Option<Workspace> {
+    session
+        .get_workspace(name)
+        .cloned()
+        .or_else(|| workspace_for_uri(session, origin).filter(|workspace| workspace.name == name))
+}
+
+/// Find workspace containing `uri`.
+/// The innermost one is chosen from the workspace declared with `root.toml`, workspace folders
+/// of the client and configured workspaces.
+pub fn workspace_for_uri(session: &Session, uri: &Url) -> Option<Workspace> {
+    let declared = session.declared_workspace(uri);
+    let opened = session.find_workspace(uri).cloned();
+    match (declared, opened) {
+        (Some(declared), Some(opened)) if opened.path.starts_with(&declared.path) => {
+            Some(if opened.path == declared.path { declared } else { opened })
+        }
+        (declared, opened) => declared.or(opened),
+    }
 }
 
-fn find_workspace_for_uri(uri: &Url) -> Option<Workspace> {
+pub fn find_workspace_for_uri(uri: &Url) -> Option<Workspace> {
     let path = uri.to_file_path().ok()?;
     fn find_in_parent_dirs(path: &Path, target_file_name: &str) -> Option<PathBuf> {
         if path.file_name().unwrap_or_default() == target_file_name {
@@ -164,8 +309,66 @@ fn find_workspace_for_uri(uri: &Url) -> Option<Workspace> {
 
         None
     }
-    if let Some(path) = find_in_parent_dirs(&path, "root.toml") {
-        return Some(Workspace::from(path));
+    if let Some(path) = find_in_parent_dirs(&path, ROOT_FILE_NAME) {
+        return Some(Workspace::load(path.clone()).unwrap_or_else(|err| {
+            log::error!("can't load workspace metadata: {err}");
+            Workspace::from(path)
+        }));
     }
     return None;
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{document::Document, session::test::test_session};
+
+    #[test]
+    fn follow_scoped_links() {
+        let (mut session, root) = test_session();
+        let path = root.join("chain.norg");
+        let url = Url::from_file_path(&path).unwrap();
+        session
+            .insert_document(url.clone(), Document::try_from(path.as_path()).unwrap())
+            .unwrap();
+        let targets = |pos| {
+            definition(&session, url.clone(), pos)
+                .unwrap()
+                .into_iter()
+                .map(|loc| (loc.uri, loc.range.start.line))
+                .collect::<Vec<_>>()
+        };
+        // `** c` is searched from sub-headings of `* b`
+        assert_eq!(targets(Position::new(0, 3)), vec![(url.clone(), 7)]);
+        // links without file part point to the document itself
+        assert_eq!(targets(Position::new(1, 2)), vec![(url.clone(), 4)]);
+        // wiki links match headings from every file, the document itself first
+        let alpha = Url::from_file_path(root.join("alpha.norg")).unwrap();
+        assert_eq!(
+            targets(Position::new(2, 3)),
+            vec![(url.clone(), 8), (alpha, 0)]
+        );
+    }
+
+    #[test]
+    fn unknown_link_workspace() {
+        let session = Session::default();
+        let origin = Url::parse("file:///notes/index.norg").unwrap();
+        let target = |root| LinkDestination::Scoped {
+            file: Some(NorgFile {
+                root: Some(root),
+                path: "foo".to_string(),
+            }),
+            scope: vec![],
+        };
+        let err = check_link_workspace(
+            &session,
+            &origin,
+            &target(LinkWorkspace::Workspace("nope".to_string())),
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "unknown workspace `$nope`");
+        // `$/` is resolved later from the workspace containing the document
+        assert!(check_link_workspace(&session, &origin, &target(LinkWorkspace::Current)).is_ok());
+    }
+}
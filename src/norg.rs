@@ -1,23 +1,31 @@
 use std::path::Path;
 
 use anyhow::{anyhow, Context};
-use tree_sitter::Node;
+use lsp_types::Url;
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+use tree_sitter::{InputEdit, Node};
 
-#[derive(PartialEq)]
+use crate::tree_sitter::{edit_range, RopeExt as _};
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Linkable {
     Link {
         target: LinkDestination,
+        /// range of the text inside the braces
+        target_range: tree_sitter::Range,
         markup: Option<String>,
         range: tree_sitter::Range,
     },
     Anchor {
         target: Option<LinkDestination>,
+        target_range: Option<tree_sitter::Range>,
         markup: String,
         range: tree_sitter::Range,
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum LinkDestination {
     Uri(String),
     Scoped {
@@ -26,13 +34,13 @@ pub enum LinkDestination {
     },
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct NorgFile {
     pub root: Option<LinkWorkspace>,
     pub path: String,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum LinkWorkspace {
     /// $/
     Current,
@@ -40,7 +48,7 @@ pub enum LinkWorkspace {
     Workspace(String),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum LinkScope {
     Heading(u16, String),
     WikiHeading(String),
@@ -53,33 +61,59 @@ impl Linkable {
             Linkable::Anchor { range, .. } => *range,
         }
     }
-    pub fn try_from_node<'src>(node: Node<'_>, source: &'src [u8]) -> anyhow::Result<Self> {
+    /// shift ranges by the edit of the document
+    pub fn edit(&mut self, edit: &InputEdit) {
+        match self {
+            Linkable::Link {
+                target_range,
+                range,
+                ..
+            } => {
+                edit_range(target_range, edit);
+                edit_range(range, edit);
+            }
+            Linkable::Anchor {
+                target_range,
+                range,
+                ..
+            } => {
+                if let Some(target_range) = target_range {
+                    edit_range(target_range, edit);
+                }
+                edit_range(range, edit);
+            }
+        }
+    }
+    pub fn try_from_node(node: Node<'_>, source: &Rope) -> anyhow::Result<Self> {
         match node.kind() {
             "link" => {
-                let target = node
+                let target_node = node
                     .child_by_field_name("target")
-                    .context("can't find 'target' field from node")
-                    .and_then(|node| LinkDestination::try_from_node(node, source))?;
+                    .context("can't find 'target' field from node")?;
+                let target = LinkDestination::try_from_node(target_node, source)?;
                 let markup = node
                     .child_by_field_name("markup")
-                    .map(|node| node.utf8_text(source).unwrap().to_string());
+                    .map(|node| source.node_text(node));
                 Ok(Self::Link {
                     target,
+                    target_range: destination_range(node, target_node, source),
                     markup,
                     range: node.range(),
                 })
             }
             "anchor" => {
-                let target = node
-                    .child_by_field_name("target")
+                let target_node = node.child_by_field_name("target");
+                let target = target_node
                     .map(|node| LinkDestination::try_from_node(node, source))
                     .transpose()?;
                 let markup = node
                     .child_by_field_name("markup")
-                    .map(|node| node.utf8_text(source).unwrap().to_string())
+                    .map(|node| source.node_text(node))
                     .context("can't find 'markup' field from node")?;
                 Ok(Self::Anchor {
                     target,
+                    target_range: target_node
+                        .map(|target_node| destination_range(node, target_node, source)),
                     markup,
                     range: node.range(),
                 })
@@ -89,6 +123,89 @@ impl Linkable {
     }
 }
 
+/// Range between the braces around the link target node.
+/// (e.g. `:file:* heading` from `{:file:* heading}`)
+fn destination_range(node: Node, target: Node, source: &Rope) -> tree_sitter::Range {
+    let range = target.range();
+    let start_byte = source
+        .byte_slice(node.start_byte()..range.start_byte)
+        .to_string()
+        .rfind('{')
+        .map_or(range.start_byte, |i| node.start_byte() + i + 1);
+    let end_byte = source
+        .byte_slice(range.end_byte..node.end_byte())
+        .to_string()
+        .find('}')
+        .map_or(range.end_byte, |i| range.end_byte + i);
+    // braces and target are always in same line
+    tree_sitter::Range {
+        start_byte,
+        end_byte,
+        start_point: tree_sitter::Point {
+            row: range.start_point.row,
+            column: range.start_point.column - (range.start_byte - start_byte),
+        },
+        end_point: tree_sitter::Point {
+            row: range.end_point.row,
+            column: range.end_point.column + (end_byte - range.end_byte),
+        },
+    }
+}
+
+impl NorgFile {
+    /// Parse file part of the link target. (e.g. `$/folder/file`)
+    pub fn from_raw(raw_path: &str) -> Self {
+        let (workspace, path) = if let Some(raw_path) = raw_path.strip_prefix("/") {
+            (Some(LinkWorkspace::Current), raw_path)
+        } else if let Some(raw_path) = raw_path.strip_prefix("$/") {
+            (Some(LinkWorkspace::Current), raw_path)
+        } else if let Some(raw_path) = raw_path.strip_prefix("$") {
+            let (name, path) = raw_path.split_once('/').unwrap_or((raw_path, ""));
+            let path = path.trim_start_matches('/');
+            if name.is_empty() {
+                // lone `$` while the link is still being typed
+                (Some(LinkWorkspace::Current), path)
+            } else {
+                (Some(LinkWorkspace::Workspace(name.to_string())), path)
+            }
+        } else {
+            (None, raw_path)
+        };
+        Self {
+            root: workspace,
+            path: path.to_string(),
+        }
+    }
+    /// Build a file link to the norg file at `path`, written relative to `base` directory
+    pub fn relative_to(base: &Path, path: &Path, root: Option<LinkWorkspace>) -> Option<Self> {
+        let path = relative_path(base, &path.with_extension(""))?;
+        Some(Self { root, path })
+    }
+}
+
+/// Get relative path from `base` directory to `path` joined with `/`.
+/// Returns `None` when two paths don't share the same root.
+pub fn relative_path(base: &Path, path: &Path) -> Option<String> {
+    let base: Vec<_> = base.components().collect();
+    let path: Vec<_> = path.components().collect();
+    let common = base
+        .iter()
+        .zip(path.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    if common == 0 {
+        return None;
+    }
+    let parts: Vec<_> = std::iter::repeat_n("..".into(), base.len() - common)
+        .chain(
+            path[common..]
+                .iter()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned()),
+        )
+        .collect();
+    Some(parts.join("/"))
+}
+
 impl ToString for NorgFile {
     fn to_string(&self) -> String {
         if let Some(root) = &self.root {
@@ -102,8 +219,8 @@ impl ToString for NorgFile {
 impl ToString for LinkWorkspace {
     fn to_string(&self) -> String {
         match self {
-            Self::Current => "/".to_owned(),
-            Self::Workspace(workspace) => format!("!${workspace}/"),
+            Self::Current => "$/".to_owned(),
+            Self::Workspace(workspace) => format!("${workspace}/"),
         }
     }
 }
@@ -125,16 +242,18 @@ impl ToString for LinkDestination {
                 file: Some(file),
                 scope,
             } => {
-                file.to_string()
+                format!(":{}:", file.to_string())
                     + &scope
                         .iter()
-                        .map(|s| format!(" : {}", s.to_string()))
-                        .collect::<String>()
+                        .map(|s| s.to_string())
+                        .collect::<Vec<_>>()
+                        .join(" : ")
             }
             Self::Scoped { file: None, scope } => scope
                 .iter()
-                .map(|s| format!(" : {}", s.to_string()))
-                .collect(),
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+                .join(" : "),
         }
     }
 }
@@ -153,10 +272,10 @@ impl<'a> Iterator for ScopedLinkTargetIterator<'a> {
 }
 
 impl LinkDestination {
-    pub fn try_from_node<'src>(node: Node<'_>, source: &'src [u8]) -> anyhow::Result<Self> {
+    pub fn try_from_node(node: Node<'_>, source: &Rope) -> anyhow::Result<Self> {
         match node.kind() {
             "raw_target" => {
-                Ok(Self::Uri(node.utf8_text(source).unwrap().to_string()))
+                Ok(Self::Uri(source.node_text(node)))
             }
             "scoped_target" => {
                 let mut iter = ScopedLinkTargetIterator {
@@ -165,27 +284,8 @@ impl LinkDestination {
                 let file =
                     if iter.peek().ok_or(anyhow!("scope is empty"))?.kind() == "raw_target" {
                         let first = iter.next().unwrap();
-                        let raw_path = first.utf8_text(source).unwrap();
-                        let (workspace, path) = if let Some(raw_path) = raw_path.strip_prefix("/") {
-                            (Some(LinkWorkspace::Current), raw_path)
-                        } else if let Some(raw_path) = raw_path.strip_prefix("$/") {
-                            (Some(LinkWorkspace::Current), raw_path)
-                        } else if raw_path.starts_with("$") {
-                            let name = Path::new(&raw_path[1..])
-                                .iter()
-                                .next()
-                                .unwrap()
-                                .to_string_lossy()
-                                .into_owned();
-                            let path = raw_path.trim_start_matches(&format!("${name}"));
-                            (Some(LinkWorkspace::Workspace(name)), path)
-                        } else {
-                            (None, raw_path)
-                        };
-                        Some(NorgFile {
-                            root: workspace,
-                            path: path.to_string(),
-                        })
+                        let raw_path = source.node_text(first);
+                        Some(NorgFile::from_raw(&raw_path))
                     } else {
                         None
                     };
@@ -197,12 +297,12 @@ impl LinkDestination {
                             let level =
                                 prefix_node.range().end_byte - prefix_node.range().start_byte;
                             let text_node = node.child_by_field_name("text").unwrap();
-                            let text = text_node.utf8_text(source).unwrap().to_string();
+                            let text = source.node_text(text_node);
                             LinkScope::Heading(level as u16, text)
                         }
                         "wiki_target" => {
                             let text_node = node.child_by_field_name("text").unwrap();
-                            let text = text_node.utf8_text(source).unwrap().to_string();
+                            let text = source.node_text(text_node);
                             LinkScope::WikiHeading(text)
                         }
                         n => return Err(anyhow!("invalid node {n} for link scope")),
@@ -214,27 +314,93 @@ impl LinkDestination {
         }
     }
 
-    // pub fn update_uri(&mut self, new_uri: &str) -> anyhow::Result<()> {
-    //     match self {
-    //         Self::Uri(uri) => {
-    //             *uri = new_uri.to_owned();
-    //             Ok(())
-    //         }
-    //         #[allow(unused_variables)]
-    //         Self::Scoped {
-    //             file: Some(NorgFile { root, path }),
-    //             scope,
-    //         } => {
-    //             // TODO:
-    //             // 1. find workspace and relative path of `new_uri` from dirman
-    //             // 2. update `root` and `path` with result
-    //             // Url::parse(&new_uri).unwrap().path().starts_with(new_uri);
-    //             todo!("update norg_file type link destination")
-    //         }
-    //         Self::Scoped {
-    //             file: None,
-    //             scope: _,
-    //         } => Err(anyhow!("Link has no path value")),
-    //     }
-    // }
+    /// Update file part of the destination to point `new_path`.
+    /// `base` is the directory that file part is relative to. (e.g. workspace root for `$/`)
+    pub fn update_uri(&mut self, new_path: &Path, base: &Path) -> anyhow::Result<()> {
+        match self {
+            Self::Uri(uri) => {
+                *uri = Url::from_file_path(new_path)
+                    .map_err(|_| anyhow!("invalid path {}", new_path.display()))?
+                    .to_string();
+                Ok(())
+            }
+            Self::Scoped {
+                file: Some(file),
+                scope: _,
+            } => {
+                let new_file = NorgFile::relative_to(base, new_path, file.root.clone())
+                    .context("can't find relative path")?;
+                file.path = if file.path.ends_with(".norg") {
+                    new_file.path + ".norg"
+                } else {
+                    new_file.path
+                };
+                Ok(())
+            }
+            Self::Scoped {
+                file: None,
+                scope: _,
+            } => Err(anyhow!("Link has no path value")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_relative_path() {
+        let base = Path::new("/notes/folder");
+        assert_eq!(
+            relative_path(base, Path::new("/notes/folder/foo")),
+            Some("foo".to_string())
+        );
+        assert_eq!(
+            relative_path(base, Path::new("/notes/folder/asdf/file")),
+            Some("asdf/file".to_string())
+        );
+        assert_eq!(
+            relative_path(base, Path::new("/notes/index")),
+            Some("../index".to_string())
+        );
+    }
+
+    #[test]
+    fn norg_file_from_raw() {
+        assert_eq!(
+            NorgFile::from_raw("$notes/folder/foo"),
+            NorgFile {
+                root: Some(LinkWorkspace::Workspace("notes".to_string())),
+                path: "folder/foo".to_string(),
+            }
+        );
+        assert_eq!(
+            NorgFile::from_raw("$"),
+            NorgFile {
+                root: Some(LinkWorkspace::Current),
+                path: "".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn update_uri() {
+        let mut target = LinkDestination::Scoped {
+            file: Some(NorgFile {
+                root: None,
+                path: "folder/foo".to_string(),
+            }),
+            scope: vec![LinkScope::Heading(1, "heading".to_string())],
+        };
+        target
+            .update_uri(Path::new("/notes/other/foo.norg"), Path::new("/notes/folder"))
+            .unwrap();
+        assert_eq!(target.to_string(), ":../other/foo:* heading");
+        let mut target = LinkDestination::Uri("file:///notes/foo.norg".to_string());
+        target
+            .update_uri(Path::new("/notes/bar.norg"), Path::new("/notes"))
+            .unwrap();
+        assert_eq!(target.to_string(), "file:///notes/bar.norg");
+    }
 }
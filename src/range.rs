@@ -1,3 +1,46 @@
+use lsp_types::PositionEncodingKind;
+use ropey::Rope;
+
+/// Unit of `character` in LSP positions, negotiated with the client.
+/// Columns inside the server are always bytes and converted with the document text when
+/// positions are received from or sent to the client.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PositionEncoding {
+    Utf8,
+    /// default of the protocol which every client supports
+    #[default]
+    Utf16,
+    Utf32,
+}
+impl PositionEncoding {
+    /// Choose from the encodings supported by the client. UTF-8 is preferred as it needs no
+    /// conversion.
+    pub fn negotiate(supported: &[PositionEncodingKind]) -> Self {
+        if supported.contains(&PositionEncodingKind::UTF8) {
+            Self::Utf8
+        } else if supported.contains(&PositionEncodingKind::UTF32) {
+            Self::Utf32
+        } else {
+            Self::Utf16
+        }
+    }
+    pub fn kind(self) -> PositionEncodingKind {
+        match self {
+            Self::Utf8 => PositionEncodingKind::UTF8,
+            Self::Utf16 => PositionEncodingKind::UTF16,
+            Self::Utf32 => PositionEncodingKind::UTF32,
+        }
+    }
+    /// length of `c` in code units of this encoding
+    fn len_char(self, c: char) -> usize {
+        match self {
+            Self::Utf8 => c.len_utf8(),
+            Self::Utf16 => c.len_utf16(),
+            Self::Utf32 => 1,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Position {
     pub row: usize,
@@ -7,6 +50,35 @@ impl Position {
     pub fn new(row: usize, column: usize) -> Self {
         Self { row, column }
     }
+    /// Convert position from the client to byte column in `text`.
+    /// Column past the end of the line is clamped to the line end.
+    pub fn from_lsp(pos: lsp_types::Position, text: &Rope, encoding: PositionEncoding) -> Self {
+        let row = pos.line as usize;
+        let Some(line) = text.get_line(row) else {
+            return Self::new(row, 0);
+        };
+        let mut units = 0;
+        let mut column = 0;
+        for c in line.chars() {
+            if units >= pos.character as usize || c == '\n' || c == '\r' {
+                break;
+            }
+            units += encoding.len_char(c);
+            column += c.len_utf8();
+        }
+        Self::new(row, column)
+    }
+    /// Convert position in `text` to the position for the client.
+    pub fn to_lsp(&self, text: &Rope, encoding: PositionEncoding) -> lsp_types::Position {
+        let character = match text
+            .get_line(self.row)
+            .and_then(|line| line.get_byte_slice(..self.column.min(line.len_bytes())))
+        {
+            Some(prefix) => prefix.chars().map(|c| encoding.len_char(c)).sum(),
+            None => self.column,
+        };
+        lsp_types::Position::new(self.row as u32, character as u32)
+    }
 }
 impl From<lsp_types::Position> for Position {
     fn from(pos: lsp_types::Position) -> Self {
@@ -53,6 +125,22 @@ pub struct Range {
     pub start: Position,
     pub end: Position,
 }
+impl Range {
+    /// Convert range from the client to byte columns in `text`
+    pub fn from_lsp(range: lsp_types::Range, text: &Rope, encoding: PositionEncoding) -> Self {
+        Self {
+            start: Position::from_lsp(range.start, text, encoding),
+            end: Position::from_lsp(range.end, text, encoding),
+        }
+    }
+    /// Convert range in `text` to the range for the client
+    pub fn to_lsp(&self, text: &Rope, encoding: PositionEncoding) -> lsp_types::Range {
+        lsp_types::Range {
+            start: self.start.to_lsp(text, encoding),
+            end: self.end.to_lsp(text, encoding),
+        }
+    }
+}
 impl From<Position> for Range {
     fn from(pos: Position) -> Self {
         Self { start: pos.clone(), end: pos }
@@ -82,3 +170,34 @@ impl Into<lsp_types::Range> for Range {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn convert_multibyte_columns() {
+        let text = Rope::from_str("* 제목 😀 end\nnext\n");
+        // `e` of `end` is at byte 14, UTF-16 unit 8, UTF-32 unit 7
+        let cases = [
+            (PositionEncoding::Utf8, 14),
+            (PositionEncoding::Utf16, 8),
+            (PositionEncoding::Utf32, 7),
+        ];
+        for (encoding, character) in cases {
+            let pos = Position::from_lsp(lsp_types::Position::new(0, character), &text, encoding);
+            assert_eq!((pos.row, pos.column), (0, 14));
+            assert_eq!(
+                pos.to_lsp(&text, encoding),
+                lsp_types::Position::new(0, character)
+            );
+        }
+        // clamped to the end of the line
+        let pos = Position::from_lsp(
+            lsp_types::Position::new(1, 10),
+            &text,
+            PositionEncoding::Utf16,
+        );
+        assert_eq!((pos.row, pos.column), (1, 4));
+    }
+}
@@ -0,0 +1,439 @@
+use std::{collections::HashMap, ops, path::PathBuf};
+
+use lsp_types::{Position, Range, TextEdit, Url, WorkspaceEdit};
+use neorg_dirman::workspace::Workspace;
+
+use crate::{
+    document::{find_heading, Heading, ResolvedLinkable},
+    neorg::{follow_link_file, follow_link_target, link_base_dir, workspace_for_uri},
+    norg::{LinkDestination, LinkScope, NorgFile},
+    session::Session,
+    syntax::{anchor_markup_at, classify_for_decl, heading_title_at, Syntax},
+    tree_sitter::ToLspRange as _,
+};
+
+/// Check if symbol at `pos` can be renamed. Returns the range of the name to rename.
+pub fn prepare_rename(session: &Session, uri: &Url, pos: Position) -> Option<Range> {
+    let doc = session.get_document(uri)?;
+    match classify_for_decl(&doc.tree, pos.into())? {
+        Syntax::Section(node) => {
+            let title_node = heading_title_at(node, pos.into())?;
+            Some(title_node.range().to_lsp_range())
+        }
+        Syntax::AnchorDefinition(node) | Syntax::AnchorReference(node) => {
+            let markup_node = anchor_markup_at(node, pos.into())?;
+            Some(markup_node.range().to_lsp_range())
+        }
+        _ => None,
+    }
+}
+
+pub fn rename(
+    session: &Session,
+    uri: &Url,
+    pos: Position,
+    new_name: &str,
+) -> Option<WorkspaceEdit> {
+    let doc = session.get_document(uri)?;
+    match classify_for_decl(&doc.tree, pos.into())? {
+        Syntax::Section(node) => {
+            // links to the heading can't hold these characters
+            if new_name.contains('}') || new_name.contains('\n') {
+                return None;
+            }
+            let title_node = heading_title_at(node, pos.into())?;
+            let headings = doc.get_headings();
+            let heading = headings
+                .iter()
+                .flat_map(Heading::iter)
+                .find(|heading| heading.title_range == title_node.range())?;
+            Some(rename_heading(session, uri, &headings, heading, new_name))
+        }
+        Syntax::AnchorDefinition(node) | Syntax::AnchorReference(node) => {
+            if new_name.contains(']') || new_name.contains('\n') {
+                return None;
+            }
+            let markup_node = anchor_markup_at(node, pos.into())?;
+            let markup = doc
+                .text
+                .byte_slice(markup_node.start_byte()..markup_node.end_byte())
+                .to_string();
+            let edits = doc
+                .find_anchor_markups(&markup)
+                .into_iter()
+                .map(|range| TextEdit::new(range.to_lsp_range(), new_name.to_string()))
+                .collect();
+            Some(WorkspaceEdit {
+                changes: Some(HashMap::from([(uri.clone(), edits)])),
+                ..Default::default()
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Rename heading title and all scoped links pointing to it or its sub-headings
+fn rename_heading(
+    session: &Session,
+    uri: &Url,
+    headings: &[Heading],
+    heading: &Heading,
+    new_name: &str,
+) -> WorkspaceEdit {
+    let mut changes: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+    changes.entry(uri.clone()).or_default().push(TextEdit::new(
+        heading.title_range.to_lsp_range(),
+        new_name.to_string(),
+    ));
+    for (origin, link) in workspace_linkables(session, uri) {
+        let Some(target_range) = link.target_range else {
+            continue;
+        };
+        let LinkDestination::Scoped { file, scope } = &link.target else {
+            continue;
+        };
+        let points_to_doc = match (file, scope.first()) {
+            (_, None) => false,
+            // wiki links can point to headings from other files
+            (None, Some(LinkScope::WikiHeading(_))) => {
+                follow_link_target(session, &origin, &link.target)
+                    .iter()
+                    .any(|loc| loc.uri == *uri)
+            }
+            _ => follow_link_file(session, &origin, &link.target).as_ref() == Some(uri),
+        };
+        if !points_to_doc {
+            continue;
+        }
+        // any scope in the chain can point to the renamed heading
+        let renamed: Vec<_> = (0..scope.len())
+            .filter(|&i| {
+                find_heading(headings, &scope[..=i])
+                    .is_some_and(|found| found.title_range == heading.title_range)
+            })
+            .collect();
+        if renamed.is_empty() {
+            continue;
+        }
+        // only replace the titles so the rest of the link is kept as written
+        let Some(written) = session.get_text(&origin).and_then(|text| {
+            text.get_byte_slice(target_range.start_byte..target_range.end_byte)
+                .map(|slice| slice.to_string())
+        }) else {
+            continue;
+        };
+        let Some(offsets) = scope_title_offsets(&written, file.is_some(), scope) else {
+            log::error!("can't locate link scopes of {written:?} in {origin}");
+            continue;
+        };
+        let start = target_range.to_lsp_range().start;
+        let edits = changes.entry(origin).or_default();
+        for i in renamed {
+            let offsets = &offsets[i];
+            let range = Range::new(
+                Position::new(start.line, start.character + offsets.start as u32),
+                Position::new(start.line, start.character + offsets.end as u32),
+            );
+            edits.push(TextEdit::new(range, new_name.to_string()));
+        }
+    }
+    WorkspaceEdit {
+        changes: Some(changes),
+        ..Default::default()
+    }
+}
+
+/// Find byte ranges of the scope titles in the written link target relative to its start.
+/// (e.g. `T` from `:file:* T`)
+fn scope_title_offsets(
+    written: &str,
+    has_file: bool,
+    scope: &[LinkScope],
+) -> Option<Vec<ops::Range<usize>>> {
+    let mut cursor = 0;
+    if has_file {
+        // skip `:file:`
+        cursor = written.get(1..)?.find(':')? + 2;
+    }
+    // move cursor over the characters matching `pred`
+    let skip = |cursor: usize, pred: &dyn Fn(char) -> bool| {
+        let rest = &written[cursor..];
+        cursor + rest.len() - rest.trim_start_matches(pred).len()
+    };
+    let mut offsets = vec![];
+    for (i, scope) in scope.iter().enumerate() {
+        let (prefix, title) = match scope {
+            LinkScope::Heading(_, title) => ('*', title),
+            LinkScope::WikiHeading(title) => ('?', title),
+        };
+        if i > 0 {
+            // scope separator ` : `
+            cursor = skip(cursor, &|c| c == ':' || c.is_whitespace());
+        }
+        cursor = skip(cursor, &|c| c == prefix || c.is_whitespace());
+        if !written[cursor..].starts_with(title.as_str()) {
+            return None;
+        }
+        offsets.push(cursor..cursor + title.len());
+        cursor += title.len();
+    }
+    Some(offsets)
+}
+
+/// Collect linkables from the workspace containing `uri`.
+/// Falls back to linkables of the document itself when it isn't in any workspace.
+fn workspace_linkables(session: &Session, uri: &Url) -> Vec<(Url, ResolvedLinkable)> {
+    if let Some(workspace) = workspace_for_uri(session, uri) {
+        session.workspace_linkables(&workspace)
+    } else {
+        session
+            .get_document(uri)
+            .map(|doc| {
+                doc.links
+                    .iter()
+                    .map(|link| (uri.clone(), link.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Rewrite all links broken by renaming files or directories.
+/// This includes links pointing to moved files and relative links written
+/// inside moved files.
+///
+/// * `files`: list of `(old_uri, new_uri)` pairs
+pub fn will_rename_files(session: &Session, files: &[(Url, Url)]) -> WorkspaceEdit {
+    let moved = expand_renames(files);
+    let mut workspaces: Vec<Workspace> = vec![];
+    for (old_uri, _) in files {
+        let Some(workspace) = workspace_for_uri(session, old_uri) else {
+            continue;
+        };
+        if !workspaces.iter().any(|w| w.path == workspace.path) {
+            workspaces.push(workspace);
+        }
+    }
+    let mut changes: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+    for workspace in workspaces.iter() {
+        for (origin, link) in session.workspace_linkables(workspace) {
+            let Some(target_range) = link.target_range else {
+                continue;
+            };
+            let root = match &link.target {
+                LinkDestination::Uri(_) => None,
+                LinkDestination::Scoped {
+                    file: Some(NorgFile { root, .. }),
+                    ..
+                } => root.as_ref(),
+                LinkDestination::Scoped { file: None, .. } => continue,
+            };
+            let Some(target_uri) = follow_link_file(session, &origin, &link.target) else {
+                continue;
+            };
+            let origin_moved = moved.get(&origin);
+            let target_moved = moved.get(&target_uri);
+            if origin_moved.is_none() && target_moved.is_none() {
+                continue;
+            }
+            let Some(new_path) = target_moved
+                .cloned()
+                .or_else(|| target_uri.to_file_path().ok())
+            else {
+                continue;
+            };
+            let base = match (root, origin_moved) {
+                // relative links are written from new location of the origin
+                (None, Some(new_origin)) => new_origin.parent().map(|dir| dir.to_path_buf()),
+                _ => link_base_dir(session, &origin, root),
+            };
+            let Some(base) = base else {
+                continue;
+            };
+            let mut target = link.target.clone();
+            if let Err(err) = target.update_uri(&new_path, &base) {
+                log::error!("can't update link in {origin}: {err}");
+                continue;
+            }
+            if target == link.target {
+                continue;
+            }
+            changes.entry(origin).or_default().push(TextEdit::new(
+                target_range.to_lsp_range(),
+                target.to_string(),
+            ));
+        }
+    }
+    WorkspaceEdit {
+        changes: Some(changes),
+        ..Default::default()
+    }
+}
+
+/// Expand directory renames to every norg file under the directory.
+/// Returns map from old uri to new path of each moved file.
+fn expand_renames(files: &[(Url, Url)]) -> HashMap<Url, PathBuf> {
+    let mut moved = HashMap::new();
+    for (old_uri, new_uri) in files {
+        let (Ok(old_path), Ok(new_path)) = (old_uri.to_file_path(), new_uri.to_file_path()) else {
+            continue;
+        };
+        if old_path.is_dir() {
+            for file in Workspace::from(old_path.clone()).iter_files() {
+                let Ok(relative) = file.strip_prefix(&old_path) else {
+                    continue;
+                };
+                let Ok(url) = Url::from_file_path(&file) else {
+                    continue;
+                };
+                moved.insert(url, new_path.join(relative));
+            }
+        } else {
+            moved.insert(old_uri.clone(), new_path);
+        }
+    }
+    moved
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{document::Document, session::test::test_session};
+
+    /// new texts of the edits in `path` in sorted order
+    fn new_texts(edit: &WorkspaceEdit, path: &Path) -> Vec<String> {
+        let url = Url::from_file_path(path).unwrap();
+        let mut texts: Vec<_> = edit.changes.as_ref().unwrap()[&url]
+            .iter()
+            .map(|edit| edit.new_text.clone())
+            .collect();
+        texts.sort();
+        texts
+    }
+
+    #[test]
+    fn rename_folder() {
+        let (session, root) = test_session();
+        let files = [(
+            Url::from_file_path(root.join("folder")).unwrap(),
+            Url::from_file_path(root.join("archive/folder")).unwrap(),
+        )];
+        let edit = will_rename_files(&session, &files);
+        // inbound links
+        assert_eq!(
+            new_texts(&edit, &root.join("index.norg")),
+            vec![
+                ":$/archive/folder/asdf/file:",
+                ":$/archive/folder/foo:",
+                ":archive/folder/foo:",
+                ":archive/folder/foo:",
+            ]
+        );
+        assert_eq!(
+            new_texts(&edit, &root.join("baz.norg")),
+            vec![":archive/folder/foo:"]
+        );
+        // relative links from the moved files. `{:foo:}` moves together with its target.
+        assert_eq!(
+            new_texts(&edit, &root.join("folder/bar.norg")),
+            vec![":../../baz:"]
+        );
+    }
+
+    #[test]
+    fn rename_heading_links() {
+        let (mut session, root) = test_session();
+        let path = root.join("folder/foo.norg");
+        let url = Url::from_file_path(&path).unwrap();
+        session
+            .insert_document(url.clone(), Document::try_from(path.as_path()).unwrap())
+            .unwrap();
+        let edit = rename(&session, &url, Position::new(0, 4), "hello").unwrap();
+        let changes = edit.changes.unwrap();
+        let line_range =
+            |line, start, end| Range::new(Position::new(line, start), Position::new(line, end));
+        let ranges = |url: &Url| {
+            let mut ranges: Vec<_> = changes[url]
+                .iter()
+                .inspect(|edit| assert_eq!(edit.new_text, "hello"))
+                .map(|edit| edit.range)
+                .collect();
+            ranges.sort_by_key(|range| range.start);
+            ranges
+        };
+        // the heading itself and `{* T}`
+        assert_eq!(
+            ranges(&url),
+            vec![line_range(0, 2, 23), line_range(2, 3, 24)]
+        );
+        // `{:file:* T}` and `{? T}`
+        let bar = Url::from_file_path(root.join("folder/bar.norg")).unwrap();
+        assert_eq!(
+            ranges(&bar),
+            vec![line_range(2, 8, 29), line_range(3, 3, 24)]
+        );
+        assert!(rename(&session, &url, Position::new(0, 4), "a}b").is_none());
+    }
+
+    #[test]
+    fn rename_anchors() {
+        let url = Url::parse("file:///notes/index.norg").unwrap();
+        let mut session = Session::default();
+        let text = "[anchor]{:file:}\nsee [anchor] and [anchor]\n{:file:}\n";
+        session
+            .insert_document(url.clone(), Document::new(text))
+            .unwrap();
+        let line_range =
+            |line, start, end| Range::new(Position::new(line, start), Position::new(line, end));
+        // from the definition and from a reference
+        for pos in [Position::new(0, 3), Position::new(1, 7)] {
+            let edit = rename(&session, &url, pos, "renamed").unwrap();
+            let mut edits = edit.changes.unwrap().remove(&url).unwrap();
+            edits.sort_by_key(|edit| edit.range.start);
+            assert_eq!(
+                edits,
+                vec![
+                    TextEdit::new(line_range(0, 1, 7), "renamed".to_string()),
+                    TextEdit::new(line_range(1, 5, 11), "renamed".to_string()),
+                    TextEdit::new(line_range(1, 18, 24), "renamed".to_string()),
+                ]
+            );
+        }
+        assert!(rename(&session, &url, Position::new(0, 3), "a]b").is_none());
+        assert!(rename(&session, &url, Position::new(0, 3), "a\nb").is_none());
+
+        assert_eq!(
+            prepare_rename(&session, &url, Position::new(0, 3)),
+            Some(line_range(0, 1, 7))
+        );
+        // target of the definition and links
+        assert!(prepare_rename(&session, &url, Position::new(0, 11)).is_none());
+        assert!(prepare_rename(&session, &url, Position::new(2, 3)).is_none());
+    }
+
+    #[test]
+    fn find_scope_titles() {
+        let scope = vec![
+            LinkScope::Heading(1, "T".to_string()),
+            LinkScope::Heading(2, "sub".to_string()),
+        ];
+        assert_eq!(
+            scope_title_offsets(":$/foo:* T : ** sub", true, &scope),
+            Some(vec![9..10, 16..19])
+        );
+        assert_eq!(
+            scope_title_offsets("*T:**sub", false, &scope),
+            Some(vec![1..2, 5..8])
+        );
+        let scope = vec![
+            LinkScope::WikiHeading("T".to_string()),
+            LinkScope::Heading(1, "sub".to_string()),
+        ];
+        assert_eq!(
+            scope_title_offsets("?  T : * sub", false, &scope),
+            Some(vec![3..4, 9..12])
+        );
+    }
+}
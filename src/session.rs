@@ -1,55 +1,655 @@
-use std::collections::HashMap;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
 
-use lsp_types::Url;
-use neorg_dirman::workspace::Workspace;
+use lsp_types::{Location, Url, WorkspaceEdit};
+use neorg_dirman::{
+    metadata::ROOT_FILE_NAME,
+    workspace::{Workspace, WorkspaceManager},
+};
+use norgberg::NorgBerg;
+use ropey::Rope;
 
-use crate::document::Document;
+use crate::{
+    document::{Document, DocumentSummary, Heading, ResolvedLinkable},
+    link_graph::{wiki_titles, GraphLink, HeadingIndex, LinkGraph},
+    neorg::{find_workspace_for_uri, follow_link_file, follow_link_target},
+    norg::{LinkDestination, LinkScope},
+    range::{Position, PositionEncoding, Range},
+    tree_sitter::ToLspRange as _,
+};
 
 // global server state. meant to replace state::State
-#[derive(Default)]
+/// Cloned as a snapshot for requests handled in worker threads. Clones share the index until
+/// either one is changed.
+#[derive(Default, Clone)]
 pub struct Session {
     index: Index,
-    pub workspace: Option<Workspace>,
-    // workspaces: BTreeMap<Url, NorgWorkspace>,
+    pub workspaces: Option<WorkspaceManager>,
+    /// workspace folders opened in the client
+    pub folders: Vec<Workspace>,
+    /// encoding of positions sent from and to the client
+    pub encoding: PositionEncoding,
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Index {
-    documents: HashMap<Url, Document>,
+    /// documents are shared between snapshots until they are changed
+    documents: Arc<HashMap<Url, Arc<Document>>>,
+    /// summaries of all files in workspaces, including closed ones
+    berg: Arc<NorgBerg<DocumentSummary>>,
+    /// roots of workspaces already indexed to `berg`
+    indexed_roots: HashSet<PathBuf>,
+    /// resolved links of indexed files and opened documents
+    graph: Arc<LinkGraph>,
+    /// headings of files in `graph` as they were when the links are resolved
+    headings: Arc<HeadingIndex>,
+    /// workspaces being indexed to a copy of `berg` in the background
+    indexing: Option<Vec<Workspace>>,
+    /// files indexed to `berg` while indexing in the background, merged to its result
+    changed: HashSet<PathBuf>,
+    /// workspaces to index in the background when the current indexing finishes
+    pending: Vec<Workspace>,
+    /// workspaces declared with `root.toml` by paths looked up from.
+    /// shared with snapshots since lookups don't depend on the session state.
+    declared: Arc<Mutex<HashMap<PathBuf, Option<Workspace>>>>,
 }
 
 impl Session {
-    pub fn with_workspace(workspace: Option<Workspace>) -> Self {
+    pub fn new(
+        workspaces: Option<WorkspaceManager>,
+        folders: Vec<Workspace>,
+        berg: NorgBerg<DocumentSummary>,
+    ) -> Self {
         Self {
-            workspace,
-            ..Default::default()
+            index: Index {
+                berg: Arc::new(berg),
+                ..Default::default()
+            },
+            workspaces,
+            folders,
+            encoding: PositionEncoding::default(),
         }
     }
     pub fn insert_document(&mut self, url: Url, document: Document) -> anyhow::Result<()> {
-        self.index.documents.insert(url, document);
+        Arc::make_mut(&mut self.index.documents).insert(url.clone(), Arc::new(document));
+        self.relink(&url);
         Ok(())
     }
     pub fn get_document(&self, url: &Url) -> Option<&Document> {
-        self.index.documents.get(url)
+        self.index.documents.get(url).map(Arc::as_ref)
     }
+    /// get text of opened document or read it from the file system
+    pub fn get_text(&self, url: &Url) -> Option<Cow<'_, Rope>> {
+        if let Some(doc) = self.get_document(url) {
+            return Some(Cow::Borrowed(&doc.text));
+        }
+        let path = url.to_file_path().ok()?;
+        Some(Cow::Owned(Rope::from_str(
+            &std::fs::read_to_string(path).ok()?,
+        )))
+    }
+    /// convert position from the client to byte column
+    pub fn decode_position(&self, url: &Url, pos: lsp_types::Position) -> lsp_types::Position {
+        match self.get_document(url) {
+            Some(doc) if self.encoding != PositionEncoding::Utf8 => {
+                Position::from_lsp(pos, &doc.text, self.encoding).into()
+            }
+            _ => pos,
+        }
+    }
+    /// convert range with byte columns to the range for the client
+    pub fn encode_range(&self, url: &Url, range: lsp_types::Range) -> lsp_types::Range {
+        if self.encoding == PositionEncoding::Utf8 {
+            return range;
+        }
+        match self.get_text(url) {
+            Some(text) => Range::from(range).to_lsp(&text, self.encoding),
+            None => range,
+        }
+    }
+    /// convert locations with byte columns to the locations for the client.
+    /// text of each file is read only once.
+    pub fn encode_locations(&self, locations: Vec<Location>) -> Vec<Location> {
+        if self.encoding == PositionEncoding::Utf8 {
+            return locations;
+        }
+        let mut texts = HashMap::new();
+        locations
+            .into_iter()
+            .map(|loc| {
+                let text = texts
+                    .entry(loc.uri.clone())
+                    .or_insert_with(|| self.get_text(&loc.uri));
+                let range = match text {
+                    Some(text) => Range::from(loc.range).to_lsp(text, self.encoding),
+                    None => loc.range,
+                };
+                Location::new(loc.uri, range)
+            })
+            .collect()
+    }
+    /// convert text edits with byte columns to the edits for the client
+    pub fn encode_workspace_edit(&self, mut edit: WorkspaceEdit) -> WorkspaceEdit {
+        if self.encoding == PositionEncoding::Utf8 {
+            return edit;
+        }
+        for (url, edits) in edit.changes.iter_mut().flatten() {
+            let Some(text) = self.get_text(url) else {
+                continue;
+            };
+            for edit in edits.iter_mut() {
+                edit.range = Range::from(edit.range).to_lsp(&text, self.encoding);
+            }
+        }
+        edit
+    }
+    /// get opened document or read it from the file system
+    pub fn load_document(&self, url: &Url) -> Option<Document> {
+        self.get_document(url).cloned().or_else(|| {
+            let path = url.to_file_path().ok()?;
+            Document::try_from(path.as_path()).ok()
+        })
+    }
+    /// find workspace by its name
+    pub fn get_workspace(&self, name: &str) -> Option<&Workspace> {
+        self.workspaces
+            .as_ref()
+            .and_then(|workspaces| workspaces.get_workspace(name))
+            .or_else(|| self.folders.iter().find(|folder| folder.name == name))
+    }
+    /// iterate workspace folders and configured workspaces
+    fn iter_workspaces(&self) -> impl Iterator<Item = &Workspace> {
+        self.folders.iter().chain(
+            self.workspaces
+                .iter()
+                .flat_map(|workspaces| workspaces.workspaces.values()),
+        )
+    }
+    /// find the innermost workspace folder or configured workspace containing `url`
+    pub fn find_workspace(&self, url: &Url) -> Option<&Workspace> {
+        let path = url.to_file_path().ok()?;
+        self.iter_workspaces()
+            .filter(|workspace| path.starts_with(&workspace.path))
+            .max_by_key(|workspace| workspace.path.components().count())
+    }
+    /// find workspace declared with `root.toml` in parent directories of `url`.
+    /// results are cached until a `root.toml` is changed.
+    pub fn declared_workspace(&self, url: &Url) -> Option<Workspace> {
+        let path = url.to_file_path().ok()?;
+        self.index
+            .declared
+            .lock()
+            .unwrap()
+            .entry(path)
+            .or_insert_with(|| find_workspace_for_uri(url))
+            .clone()
+    }
+    /// copy the index to index new workspaces in the background.
+    /// returns None if the indexing is already running or there's nothing to index.
+    pub fn start_indexing(&mut self) -> Option<(NorgBerg<DocumentSummary>, Vec<Workspace>)> {
+        if self.index.indexing.is_some() {
+            return None;
+        }
+        let mut workspaces: Vec<Workspace> = vec![];
+        let pending = std::mem::take(&mut self.index.pending);
+        let candidates: Vec<_> = self.iter_workspaces().cloned().chain(pending).collect();
+        for workspace in candidates {
+            if !self.index.indexed_roots.contains(&workspace.path)
+                && !workspaces.iter().any(|w| w.path == workspace.path)
+            {
+                workspaces.push(workspace);
+            }
+        }
+        if workspaces.is_empty() {
+            return None;
+        }
+        self.index
+            .indexed_roots
+            .extend(workspaces.iter().map(|workspace| workspace.path.clone()));
+        self.index.indexing = Some(workspaces.clone());
+        // the current index stays available until the copy is indexed
+        Some((self.index.berg.as_ref().clone(), workspaces))
+    }
+    /// put back the index updated in the background and save it to the disk.
+    /// headings of the indexed files and links of opened documents are updated at once.
+    /// returns the other indexed files, whose links are left to be resolved with
+    /// [`Session::relink_files`] in chunks not to block the messages.
+    pub fn finish_indexing(&mut self, mut berg: NorgBerg<DocumentSummary>) -> Vec<Url> {
+        // files changed during the indexing might be indexed from their old content
+        for path in std::mem::take(&mut self.index.changed) {
+            let _ = berg.index_file(&path, summarize);
+        }
+        self.index.berg = Arc::new(berg);
+        let workspaces = self.index.indexing.take().unwrap_or_default();
+        let documents = self.document_urls();
+        let mut urls: HashSet<_> = documents.iter().cloned().collect();
+        for workspace in workspaces.iter() {
+            urls.extend(
+                self.workspace_files(workspace)
+                    .into_iter()
+                    .map(|(url, _)| url),
+            );
+        }
+        // all headings are updated first so links between the files are resolved
+        for url in urls.iter() {
+            if let Some(headings) = self.indexed_headings(url) {
+                Arc::make_mut(&mut self.index.headings).insert(url.clone(), headings);
+            }
+        }
+        for url in documents {
+            urls.remove(&url);
+            self.relink_file(url);
+        }
+        self.save_index();
+        urls.into_iter().collect()
+    }
+    /// queue the workspace to index in the background unless it is already indexed.
+    /// only files changed since the last index are parsed.
+    pub fn index_workspace(&mut self, workspace: &Workspace) {
+        if self.index.indexed_roots.contains(&workspace.path)
+            || self
+                .index
+                .pending
+                .iter()
+                .any(|pending| pending.path == workspace.path)
+        {
+            return;
+        }
+        self.index.pending.push(workspace.clone());
+    }
+    /// resolve links of `url` again after it is changed.
+    /// links from other files which can point to `url` are resolved again only when headings
+    /// of `url` are added, removed or renamed. moved headings just move the link targets.
+    /// wiki links are resolved again only if they search the titles added or removed.
+    /// returns all files whose links are resolved.
+    pub fn relink(&mut self, url: &Url) -> HashSet<Url> {
+        let headings = self.indexed_headings(url);
+        let index = Arc::make_mut(&mut self.index.headings);
+        let old = match headings.clone() {
+            Some(headings) => index.insert(url.clone(), headings),
+            None => index.remove(url),
+        };
+        let mut origins = HashSet::new();
+        match (old, headings) {
+            (Some(old), Some(new)) if same_titles(&old, &new) => {
+                let moved: Vec<_> = old
+                    .iter()
+                    .flat_map(Heading::iter)
+                    .zip(new.iter().flat_map(Heading::iter))
+                    .filter(|(old, new)| old.range != new.range)
+                    .map(|(old, new)| (old.range.to_lsp_range(), new.range.to_lsp_range()))
+                    .collect();
+                if !moved.is_empty() {
+                    Arc::make_mut(&mut self.index.graph).move_targets(url, &moved);
+                }
+            }
+            (old, new) => {
+                let old = old.map(|old| wiki_titles(&old)).unwrap_or_default();
+                let new = new.as_deref().map(wiki_titles).unwrap_or_default();
+                let changed = old.symmetric_difference(&new).cloned().collect();
+                origins = self.index.graph.dependents(url, &changed);
+            }
+        }
+        origins.remove(url);
+        self.relink_file(url.clone());
+        for origin in origins.iter() {
+            self.relink_file(origin.clone());
+        }
+        origins.insert(url.clone());
+        origins
+    }
+    /// resolve links of files returned by [`Session::finish_indexing`]
+    pub fn relink_files(&mut self, urls: Vec<Url>) {
+        for url in urls {
+            self.relink_file(url);
+        }
+    }
+    /// update index of the file changed on the disk.
+    /// returns all files whose links are resolved again.
+    pub fn update_file(&mut self, url: &Url) -> HashSet<Url> {
+        self.index_file(url);
+        self.relink(url)
+    }
+    /// index the file again if it is in the indexed workspaces
+    fn index_file(&mut self, url: &Url) {
+        let Ok(path) = url.to_file_path() else {
+            return;
+        };
+        if !self
+            .index
+            .indexed_roots
+            .iter()
+            .any(|root| path.starts_with(root))
+        {
+            return;
+        }
+        let _ = Arc::make_mut(&mut self.index.berg).index_file(&path, summarize);
+        if self.index.indexing.is_some() {
+            self.index.changed.insert(path);
+        }
+    }
+    /// reload metadata of the workspace at `root` and index it again.
+    /// workspaces declared with `root.toml` are looked up again for all files, and links of
+    /// opened documents and files under `root` are resolved again.
+    pub fn reload_workspace(&mut self, root: &Path) {
+        let workspace = Workspace::load(root.to_path_buf()).unwrap_or_else(|err| {
+            log::error!("can't load workspace metadata: {err}");
+            Workspace::from(root.to_path_buf())
+        });
+        for folder in self.folders.iter_mut().filter(|folder| folder.path == root) {
+            if let Some(name) = &workspace.metadata.name {
+                folder.name = name.clone();
+            }
+            folder.metadata = workspace.metadata.clone();
+        }
+        // configured workspaces keep their names from the config
+        for configured in self
+            .workspaces
+            .iter_mut()
+            .flat_map(|workspaces| workspaces.workspaces.values_mut())
+            .filter(|configured| configured.path == root)
+        {
+            configured.metadata = workspace.metadata.clone();
+        }
+        self.index.declared.lock().unwrap().clear();
+        self.index.indexed_roots.remove(root);
+        let known = self.iter_workspaces().any(|known| known.path == root);
+        if known || root.join(ROOT_FILE_NAME).exists() {
+            self.index_workspace(&workspace);
+        }
+        // `$name/` and `$/` links can point to other files with the new metadata
+        let mut urls: HashSet<_> = self.document_urls().into_iter().collect();
+        urls.extend(
+            self.index
+                .berg
+                .iter_under(root)
+                .filter_map(|(path, _)| Url::from_file_path(path).ok()),
+        );
+        for url in urls {
+            self.relink_file(url);
+        }
+    }
+    /// urls of opened documents
+    pub fn document_urls(&self) -> Vec<Url> {
+        self.index.documents.keys().cloned().collect()
+    }
+    fn relink_file(&mut self, url: Url) {
+        let Some(links) = self.get_links(&url) else {
+            Arc::make_mut(&mut self.index.graph).remove(&url);
+            return;
+        };
+        let links = links
+            .into_iter()
+            .map(|link| GraphLink {
+                targets: follow_link_target(self, &url, &link.target),
+                target_file: match &link.target {
+                    LinkDestination::Scoped { file: None, scope }
+                        if matches!(scope.first(), Some(LinkScope::WikiHeading(_))) =>
+                    {
+                        None
+                    }
+                    target => follow_link_file(self, &url, target),
+                },
+                link,
+            })
+            .collect();
+        Arc::make_mut(&mut self.index.graph).insert(url, links);
+    }
+    /// get resolved links of the file
+    pub fn graph_links(&self, url: &Url) -> &[GraphLink] {
+        self.index.graph.links(url)
+    }
+    /// check if the file is opened, indexed or exists on the file system without reading it
+    pub fn file_exists(&self, url: &Url) -> bool {
+        if self.get_document(url).is_some() {
+            return true;
+        }
+        url.to_file_path()
+            .is_ok_and(|path| self.index.berg.get(&path).is_some() || path.is_file())
+    }
+    /// get locations of links pointing to `target`
+    pub fn backlinks(&self, target: &Location) -> &[Location] {
+        self.index.graph.backlinks(target)
+    }
+    pub fn save_index(&self) {
+        if let Err(err) = self.index.berg.save() {
+            log::error!("can't save index: {err}");
+        }
+    }
+    /// get summaries of all norg files in the workspace.
+    /// opened documents are preferred over the indexed ones.
+    pub fn workspace_files(&self, workspace: &Workspace) -> Vec<(Url, Cow<'_, DocumentSummary>)> {
+        let mut files: Vec<_> = self
+            .index
+            .documents
+            .iter()
+            .filter(|(url, _)| {
+                url.to_file_path().is_ok_and(|path| {
+                    path.starts_with(&workspace.path)
+                        && path.extension().is_some_and(|ext| ext == "norg")
+                        && !workspace.is_ignored(&path)
+                })
+            })
+            .map(|(url, doc)| (url.clone(), Cow::Owned(DocumentSummary::from(doc.as_ref()))))
+            .collect();
+        files.extend(
+            self.index
+                .berg
+                .iter_under(&workspace.path)
+                .filter_map(|(path, summary)| Some((Url::from_file_path(path).ok()?, summary)))
+                .filter(|(url, _)| !self.index.documents.contains_key(url))
+                .map(|(url, summary)| (url, Cow::Borrowed(summary))),
+        );
+        files
+    }
+    /// get all linkables in the workspace with urls of documents they are written in
+    pub fn workspace_linkables(&self, workspace: &Workspace) -> Vec<(Url, ResolvedLinkable)> {
+        self.workspace_files(workspace)
+            .into_iter()
+            .flat_map(|(url, summary)| {
+                summary
+                    .links
+                    .iter()
+                    .map(|link| (url.clone(), link.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+    /// get links of opened document or indexed file
+    fn get_links(&self, url: &Url) -> Option<Vec<ResolvedLinkable>> {
+        if let Some(doc) = self.get_document(url) {
+            return Some(doc.links.clone());
+        }
+        let path = url.to_file_path().ok()?;
+        Some(self.index.berg.get(&path)?.links.clone())
+    }
+    /// get headings of opened document, indexed file, or file read from the file system
+    pub fn get_headings(&self, url: &Url) -> Option<Vec<Heading>> {
+        self.indexed_headings(url).or_else(|| {
+            let path = url.to_file_path().ok()?;
+            Some(Document::try_from(path.as_path()).ok()?.get_headings())
+        })
+    }
+    /// get headings of opened document or indexed file
+    fn indexed_headings(&self, url: &Url) -> Option<Vec<Heading>> {
+        if let Some(doc) = self.get_document(url) {
+            return Some(doc.get_headings());
+        }
+        let path = url.to_file_path().ok()?;
+        Some(self.index.berg.get(&path)?.headings.clone())
+    }
+    /// find files with headings matched by wiki links with `title`
+    pub fn find_wiki_files(&self, title: &str) -> impl Iterator<Item = (&Url, &[Heading])> {
+        self.index.headings.find(title)
+    }
+    pub fn add_folder(&mut self, folder: Workspace) {
+        if !self.folders.iter().any(|f| f.path == folder.path) {
+            self.folders.push(folder);
+        }
+    }
+    /// remove the workspace folder and the files indexed only for it.
+    /// returns files whose links are resolved again.
+    pub fn remove_folder(&mut self, path: &Path) -> HashSet<Url> {
+        self.folders.retain(|folder| folder.path != path);
+        // configured workspaces stay indexed
+        if self
+            .iter_workspaces()
+            .any(|workspace| workspace.path == path)
+            || !self.index.indexed_roots.remove(path)
+        {
+            return HashSet::new();
+        }
+        let files: Vec<_> = self
+            .index
+            .berg
+            .iter_under(path)
+            .map(|(file, _)| file.to_path_buf())
+            .filter(|file| {
+                !self
+                    .index
+                    .indexed_roots
+                    .iter()
+                    .any(|root| file.starts_with(root))
+            })
+            .collect();
+        let mut removed = HashSet::new();
+        let mut origins: HashSet<_> = self.document_urls().into_iter().collect();
+        for file in files {
+            Arc::make_mut(&mut self.index.berg).remove(&file);
+            let Ok(url) = Url::from_file_path(&file) else {
+                continue;
+            };
+            if self.get_document(&url).is_some() {
+                continue;
+            }
+            let titles = Arc::make_mut(&mut self.index.headings)
+                .remove(&url)
+                .map(|headings| wiki_titles(&headings))
+                .unwrap_or_default();
+            origins.extend(self.index.graph.dependents(&url, &titles));
+            Arc::make_mut(&mut self.index.graph).remove(&url);
+            removed.insert(url);
+        }
+        origins.retain(|origin| !removed.contains(origin));
+        for origin in origins.iter() {
+            self.relink_file(origin.clone());
+        }
+        origins
+    }
+    /// apply changes from the client in order.
+    /// changes older than the current version of the document are rejected.
     pub fn update_document(
         &mut self,
         url: &Url,
+        version: i32,
         changes: Vec<lsp_types::TextDocumentContentChangeEvent>,
     ) -> anyhow::Result<()> {
-        let Some(doc) = self.index.documents.get_mut(url) else {
+        let Some(doc) = self.get_document(url) else {
             anyhow::bail!("document {url} doesn't exist")
         };
+        if let Some(current) = doc.version.filter(|current| version <= *current) {
+            anyhow::bail!("version {version} of {url} is not newer than {current}")
+        }
+        let doc = Arc::make_mut(
+            Arc::make_mut(&mut self.index.documents)
+                .get_mut(url)
+                .expect("document exists"),
+        );
         for change in changes.iter() {
-            if let Some(range) = change.range {
-                doc.change_range(range, &change.text);
+            match change.range {
+                Some(range) => doc.change_range(range, &change.text, self.encoding),
+                None => doc.replace_text(&change.text),
             }
         }
+        doc.version = Some(version);
         doc.update();
+        self.relink(url);
         Ok(())
     }
     pub fn remove_document(&mut self, url: &Url) -> anyhow::Result<()> {
-        self.index.documents.remove(url);
+        Arc::make_mut(&mut self.index.documents).remove(url);
+        // closed document might be saved with changes
+        self.index_file(url);
+        self.relink(url);
         Ok(())
     }
 }
+
+/// true if headings are same except for their ranges
+fn same_titles(old: &[Heading], new: &[Heading]) -> bool {
+    old.len() == new.len()
+        && old.iter().zip(new).all(|(old, new)| {
+            old.level == new.level
+                && old.title == new.title
+                && same_titles(&old.children, &new.children)
+        })
+}
+
+/// parse the file to store in the index
+pub fn summarize(_path: &Path, bytes: &[u8]) -> Option<DocumentSummary> {
+    let text = std::str::from_utf8(bytes).ok()?;
+    Some(DocumentSummary::from(&Document::new(text)))
+}
+
+#[cfg(test)]
+pub mod test {
+    use lsp_types::{Position, Range, TextDocumentContentChangeEvent};
+
+    use super::*;
+    use crate::indexer;
+
+    /// session with the `test/` workspace indexed
+    pub fn test_session() -> (Session, PathBuf) {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("test");
+        let mut session = Session::default();
+        session.index_workspace(&Workspace::from(root.clone()));
+        let (berg, workspaces) = session.start_indexing().unwrap();
+        let (sender, _) = crossbeam_channel::unbounded();
+        let berg = indexer::spawn(sender, false, berg, workspaces)
+            .recv()
+            .unwrap();
+        let urls = session.finish_indexing(berg);
+        session.relink_files(urls);
+        (session, root)
+    }
+
+    #[test]
+    fn apply_mixed_changes() {
+        let url = Url::parse("file:///notes/index.norg").unwrap();
+        let mut session = Session::default();
+        let mut document = Document::new("* heading\n");
+        document.version = Some(1);
+        session.insert_document(url.clone(), document).unwrap();
+        let changes = vec![
+            TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: "* title\n".to_string(),
+            },
+            TextDocumentContentChangeEvent {
+                range: Some(Range::new(Position::new(0, 2), Position::new(0, 7))),
+                range_length: None,
+                text: "new title".to_string(),
+            },
+        ];
+        session.update_document(&url, 2, changes).unwrap();
+        let doc = session.get_document(&url).unwrap();
+        assert_eq!(doc.text.to_string(), "* new title\n");
+        assert_eq!(doc.version, Some(2));
+
+        let stale = vec![TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: "stale".to_string(),
+        }];
+        assert!(session.update_document(&url, 2, stale).is_err());
+        assert_eq!(
+            session.get_document(&url).unwrap().text.to_string(),
+            "* new title\n"
+        );
+    }
+}
@@ -27,6 +27,27 @@ pub fn classify_node(node: Node) -> Option<Syntax> {
     Some(syntax_fn(node))
 }
 
+pub fn contains_position(node: Node, position: Position) -> bool {
+    let point = position.into();
+    node.start_position() <= point && point <= node.end_position()
+}
+
+/// Get title node of the section when `pos` is on its heading
+pub fn heading_title_at(section: Node, position: Position) -> Option<Node> {
+    let heading_node = section.child_by_field_name("heading")?;
+    if !contains_position(heading_node, position) {
+        return None;
+    }
+    heading_node.child_by_field_name("title")
+}
+
+/// Get markup node of the anchor when `pos` is on it
+pub fn anchor_markup_at(anchor: Node, position: Position) -> Option<Node> {
+    anchor
+        .child_by_field_name("markup")
+        .filter(|markup_node| contains_position(*markup_node, position))
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Syntax<'a> {
     Section(Node<'a>),
@@ -48,14 +69,18 @@ impl<'a> Syntax<'a> {
 
 #[cfg(test)]
 mod test {
-    use crate::tree_sitter::parse_norg;
+    use ropey::Rope;
+
+    use crate::tree_sitter::NorgParser;
 
     use super::*;
 
     #[test]
     fn test_classify_for_decl() {
         let text = "* _heading_ with [anchor]\n{link}";
-        let tree = parse_norg(&text, None).unwrap();
+        let tree = NorgParser::default()
+            .parse_rope(&Rope::from_str(text), None)
+            .unwrap();
         assert!(matches!(
             classify_for_decl(&tree, Position::new(0, 3)),
             Some(Syntax::Section(..)),
@@ -1,12 +1,79 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex, PoisonError},
+};
+
 use ropey::{Rope, RopeSlice};
-use tree_sitter::{Node, Parser, Query, TextProvider, Tree};
+use tree_sitter::{InputEdit, Node, Parser, Point, Query, TextProvider, Tree};
+
+use crate::range::Position;
+
+/// Norg parser kept with a document to reuse it on every change.
+/// Clones share the same parser.
+#[derive(Clone)]
+pub struct NorgParser(Arc<Mutex<Parser>>);
 
-pub fn parse_norg(text: impl AsRef<[u8]>, old_tree: Option<&Tree>) -> Option<Tree> {
-    let mut parser = Parser::new();
-    parser
-        .set_language(&tree_sitter_norg::LANGUAGE.into())
-        .expect("could not load norg parser");
-    parser.parse(&text, old_tree)
+impl Default for NorgParser {
+    fn default() -> Self {
+        let mut parser = Parser::new();
+        parser
+            .set_language(&tree_sitter_norg::LANGUAGE.into())
+            .expect("could not load norg parser");
+        Self(Arc::new(Mutex::new(parser)))
+    }
+}
+
+impl fmt::Debug for NorgParser {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NorgParser").finish_non_exhaustive()
+    }
+}
+
+impl NorgParser {
+    /// parse text fed from the chunks of `text` without copying the whole rope.
+    /// `old_tree` should be edited to match `text`.
+    pub fn parse_rope(&self, text: &Rope, old_tree: Option<&Tree>) -> Option<Tree> {
+        let mut parser = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        parser.parse_with_options(
+            &mut |byte, _| {
+                if byte >= text.len_bytes() {
+                    return &[][..];
+                }
+                let (chunk, chunk_byte, _, _) = text.chunk_at_byte(byte);
+                &chunk.as_bytes()[byte - chunk_byte..]
+            },
+            old_tree,
+            None,
+        )
+    }
+}
+
+/// shift the range by the edit like [`Tree::edit`] does to nodes.
+/// positions inside the edited range are moved to the end of the new text.
+pub fn edit_range(range: &mut tree_sitter::Range, edit: &InputEdit) {
+    edit_point(&mut range.start_byte, &mut range.start_point, edit);
+    edit_point(&mut range.end_byte, &mut range.end_point, edit);
+}
+
+fn edit_point(byte: &mut usize, point: &mut Point, edit: &InputEdit) {
+    if *byte >= edit.old_end_byte {
+        *byte = *byte - edit.old_end_byte + edit.new_end_byte;
+        *point = if point.row == edit.old_end_position.row {
+            Point {
+                row: edit.new_end_position.row,
+                column: point.column - edit.old_end_position.column
+                    + edit.new_end_position.column,
+            }
+        } else {
+            Point {
+                row: point.row - edit.old_end_position.row + edit.new_end_position.row,
+                column: point.column,
+            }
+        };
+    } else if *byte > edit.start_byte {
+        *byte = edit.new_end_byte;
+        *point = edit.new_end_position;
+    }
 }
 
 // TODO: replace these traits with actual internal structs
@@ -92,6 +159,10 @@ where
 
 pub(crate) trait RopeExt {
     fn try_byte_to_pos(&self, byte: usize) -> ropey::Result<(usize, usize)>;
+    /// byte offset of the position with byte column
+    fn try_pos_to_byte(&self, pos: &Position) -> ropey::Result<usize>;
+    /// copy text of the node
+    fn node_text(&self, node: Node) -> String;
 }
 
 impl RopeExt for Rope {
@@ -100,6 +171,12 @@ impl RopeExt for Rope {
         let col = byte - self.try_line_to_byte(row)?;
         Ok((row, col))
     }
+    fn try_pos_to_byte(&self, pos: &Position) -> ropey::Result<usize> {
+        Ok(self.try_line_to_byte(pos.row)? + pos.column)
+    }
+    fn node_text(&self, node: Node) -> String {
+        self.byte_slice(node.start_byte()..node.end_byte()).to_string()
+    }
 }
 
 pub trait ToLspRange {
@@ -219,3 +296,56 @@ impl<'a> TextProvider<&'a [u8]> for RopeProvider<'a> {
 //         // TODO: write test
 //     }
 // }
+
+/// serde (de)serializer for [`tree_sitter::Range`] to use with `#[serde(with = "...")]`
+pub mod serde_range {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use tree_sitter::{Point, Range};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(remote = "Point")]
+    struct PointDef {
+        row: usize,
+        column: usize,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(remote = "Range")]
+    struct RangeDef {
+        start_byte: usize,
+        end_byte: usize,
+        #[serde(with = "PointDef")]
+        start_point: Point,
+        #[serde(with = "PointDef")]
+        end_point: Point,
+    }
+
+    pub fn serialize<S: Serializer>(range: &Range, serializer: S) -> Result<S::Ok, S::Error> {
+        RangeDef::serialize(range, serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Range, D::Error> {
+        RangeDef::deserialize(deserializer)
+    }
+
+    /// same for `Option<tree_sitter::Range>`
+    pub mod option {
+        use super::*;
+
+        #[derive(Serialize, Deserialize)]
+        struct Wrapper(#[serde(with = "super")] Range);
+
+        pub fn serialize<S: Serializer>(
+            range: &Option<Range>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            range.map(Wrapper).serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<Range>, D::Error> {
+            Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|wrapper| wrapper.0))
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use std::{
+    collections::HashSet,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use crossbeam_channel::{Receiver, Sender};
+use lsp_server::{ErrorCode, RequestId, Response};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Fixed number of threads running queued jobs.
+/// Jobs should catch their own panics, a panicking job stops its thread.
+pub struct WorkerPool {
+    jobs: Sender<Job>,
+}
+
+impl WorkerPool {
+    /// Starts `threads` threads. They are stopped when the pool is dropped.
+    pub fn new(threads: usize) -> Self {
+        let (jobs, receiver) = crossbeam_channel::unbounded::<Job>();
+        for _ in 0..threads {
+            let receiver = receiver.clone();
+            thread::spawn(move || {
+                for job in receiver {
+                    job();
+                }
+            });
+        }
+        Self { jobs }
+    }
+
+    pub fn spawn(&self, job: impl FnOnce() + Send + 'static) {
+        // threads don't stop while the pool is alive
+        let _ = self.jobs.send(Box::new(job));
+    }
+}
+
+/// Result of a request handled in the pool. `None` if the request has no response.
+type Finished = (RequestId, Option<Response>);
+
+/// Requests handled in the pool, tracked until they are answered or canceled
+pub struct Requests {
+    pool: WorkerPool,
+    /// requests handled in the pool and not answered yet
+    pending: HashSet<RequestId>,
+    /// canceled requests whose jobs are not finished yet. queued jobs are skipped.
+    canceled: Arc<Mutex<HashSet<RequestId>>>,
+    sender: Sender<Finished>,
+    receiver: Receiver<Finished>,
+}
+
+impl Requests {
+    pub fn new(pool: WorkerPool) -> Self {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        Self {
+            pool,
+            pending: HashSet::new(),
+            canceled: Arc::new(Mutex::new(HashSet::new())),
+            sender,
+            receiver,
+        }
+    }
+
+    /// Channel receiving results of the handlers, to be passed to [`Requests::finish`]
+    pub fn finished(&self) -> Receiver<Finished> {
+        self.receiver.clone()
+    }
+
+    /// Queue `handler` of the request to the pool.
+    /// A panicking handler is answered with an internal error.
+    pub fn spawn(
+        &mut self,
+        id: RequestId,
+        handler: impl FnOnce() -> Option<Response> + Send + 'static,
+    ) {
+        self.pending.insert(id.clone());
+        let sender = self.sender.clone();
+        let canceled = self.canceled.clone();
+        self.pool.spawn(move || {
+            if canceled.lock().unwrap().remove(&id) {
+                return;
+            }
+            let resp = panic::catch_unwind(AssertUnwindSafe(handler)).unwrap_or_else(|_| {
+                Some(Response::new_err(
+                    id.clone(),
+                    ErrorCode::InternalError as i32,
+                    "request handler panicked".to_string(),
+                ))
+            });
+            let _ = sender.send((id, resp));
+        });
+    }
+
+    /// Cancel the request. Returns the response to send if it is not answered yet.
+    /// Result of the handler is dropped when it is finished.
+    pub fn cancel(&mut self, id: RequestId) -> Option<Response> {
+        if !self.pending.remove(&id) {
+            return None;
+        }
+        self.canceled.lock().unwrap().insert(id.clone());
+        Some(Response::new_err(
+            id,
+            ErrorCode::RequestCanceled as i32,
+            "canceled".to_string(),
+        ))
+    }
+
+    /// Returns the response to send for the finished handler, or None if it is canceled
+    pub fn finish(&mut self, (id, resp): Finished) -> Option<Response> {
+        if !self.pending.remove(&id) {
+            self.canceled.lock().unwrap().remove(&id);
+            return None;
+        }
+        resp
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+
+    fn ok(id: i32) -> Option<Response> {
+        Some(Response::new_ok(RequestId::from(id), ()))
+    }
+
+    #[test]
+    fn cancel_requests() {
+        let mut requests = Requests::new(WorkerPool::new(1));
+        let finished = requests.finished();
+        // block the only thread so following requests stay queued
+        let (started, running) = crossbeam_channel::bounded::<()>(0);
+        let (unblock, blocked) = crossbeam_channel::bounded::<()>(0);
+        requests.spawn(RequestId::from(0), move || {
+            let _ = started.send(());
+            let _ = blocked.recv();
+            ok(0)
+        });
+        running.recv().unwrap();
+        let ran = Arc::new(AtomicBool::new(false));
+        requests.spawn(RequestId::from(1), {
+            let ran = ran.clone();
+            move || {
+                ran.store(true, Ordering::SeqCst);
+                ok(1)
+            }
+        });
+        requests.spawn(RequestId::from(2), || ok(2));
+
+        // canceled request is answered once
+        let resp = requests.cancel(RequestId::from(1)).unwrap();
+        assert_eq!(resp.error.unwrap().code, ErrorCode::RequestCanceled as i32);
+        assert!(requests.cancel(RequestId::from(1)).is_none());
+        // running request is canceled and its late result is dropped
+        assert!(requests.cancel(RequestId::from(0)).is_some());
+        unblock.send(()).unwrap();
+
+        let (id, resp) = finished.recv().unwrap();
+        assert_eq!(id, RequestId::from(0));
+        assert!(requests.finish((id, resp)).is_none());
+        // queued job of the canceled request is skipped
+        let (id, resp) = finished.recv().unwrap();
+        assert_eq!(id, RequestId::from(2));
+        assert!(requests.finish((id, resp)).is_some());
+        assert!(!ran.load(Ordering::SeqCst));
+        assert!(requests.canceled.lock().unwrap().is_empty());
+        // answered request can't be canceled
+        assert!(requests.cancel(RequestId::from(2)).is_none());
+    }
+}
@@ -1,45 +1,29 @@
-use lsp_types::Url;
+use lsp_types::{Url, WorkspaceFolder};
 use neorg_dirman::workspace::Workspace;
 
-use crate::document::{Document, ResolvedLinkable};
-
 pub trait WorkspaceExt {
     fn get_url(&self) -> Result<Url, ()>;
-    fn iter_linkables_with(
-        &self,
-        doc_provider: impl Fn(&Url) -> Option<Document>
-    ) -> impl Iterator<Item = (Url, ResolvedLinkable)>;
-    fn iter_docs_with(
-        &self,
-        doc_provider: impl Fn(&Url) -> Option<Document>,
-    ) -> impl Iterator<Item = (Url, Document)>;
 }
 
 impl WorkspaceExt for Workspace {
     fn get_url(&self) -> Result<Url, ()> {
         Url::from_directory_path(&self.path)
     }
-    fn iter_docs_with(
-        &self,
-        doc_provider: impl Fn(&Url) -> Option<Document>,
-    ) -> impl Iterator<Item = (Url, Document)> {
-        self.iter_files()
-            .flat_map(move |path| {
-                let url = Url::from_file_path(path).ok()?;
-                let doc = doc_provider(&url).or({
-                    let path = url.to_file_path().ok()?;
-                    Document::try_from(path.as_path()).ok()
-                })?;
-                Some((url, doc))
-            })
-    }
-    fn iter_linkables_with(
-        &self,
-        doc_provider: impl Fn(&Url) -> Option<Document>,
-    ) -> impl Iterator<Item = (Url, ResolvedLinkable)> {
-        self.iter_docs_with(doc_provider)
-            .flat_map(|(url, doc)| {
-                doc.links.into_iter().map(move |rl| (url.clone(), rl))
-            })
-    }
+}
+
+/// Create workspace from the workspace folder of the client.
+/// Folder name is used as the workspace name unless it is declared in `root.toml`.
+pub fn workspace_from_folder(folder: &WorkspaceFolder) -> Option<Workspace> {
+    let path = folder.uri.to_file_path().ok()?;
+    Some(match Workspace::load(path.clone()) {
+        Ok(workspace) if workspace.metadata.name.is_some() => workspace,
+        Ok(workspace) => Workspace {
+            name: folder.name.clone(),
+            ..workspace
+        },
+        Err(err) => {
+            log::error!("can't load workspace metadata: {err}");
+            Workspace::new(folder.name.clone(), path)
+        }
+    })
 }